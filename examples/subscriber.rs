@@ -2,7 +2,7 @@ use std::time::Duration;
 use xactor::*;
 
 // This is a basic subscriber example to demonstrate usage of Sender
-// We have actor A - SubscriberParent, manages a vec of child subscribers and in this example, sets up the message producer
+// We have actor A - SubscriberParent, spawns the child subscribers and in this example, sets up the message producer
 // Actor B - (Child) Subscriber
 // Actor C - Message Producer (Being subscribed to by the child subscribers) - producing a RandomMessage every few seconds to be broadcast to subscribers
 
@@ -16,14 +16,12 @@ async fn main() -> std::io::Result<()> {
 // Subscriber Parent - A
 
 struct SubscriberParent {
-    children_subscribers: Vec<Addr<Subscriber>>,
     message_producer: Addr<MessageProducer>,
 }
 
 impl SubscriberParent {
     async fn new() -> SubscriberParent {
         SubscriberParent {
-            children_subscribers: Vec::new(),
             message_producer: MessageProducer::new().start().await.unwrap(),
         }
     }
@@ -43,23 +41,13 @@ struct InitializeChildSubscribers;
 
 #[async_trait::async_trait]
 impl Handler<InitializeChildSubscribers> for SubscriberParent {
-    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: InitializeChildSubscribers) {
-        let message_producer_addr = self.message_producer.clone();
+    async fn handle(&mut self, ctx: &mut Context<Self>, _msg: InitializeChildSubscribers) {
         let dummy_ids: Vec<i32> = vec![1, 2, 3, 4, 5];
-        let children_unstarted_actors_vec = dummy_ids.into_iter().map(move |id| {
-            let id = id.clone();
-            let addr = message_producer_addr.clone();
-
-            Subscriber::new(id, addr)
-        });
-
-        let children_addr_vec = children_unstarted_actors_vec
-            .into_iter()
-            .map(|actor| async { actor.start().await.unwrap() });
-
-        let children_addr_vec = futures::future::join_all(children_addr_vec).await;
-
-        self.children_subscribers = children_addr_vec;
+        for id in dummy_ids {
+            // Children are stopped together with the parent
+            let subscriber = Subscriber::new(id, self.message_producer.clone());
+            ctx.spawn_child(subscriber).await.unwrap();
+        }
     }
 }
 
@@ -68,8 +56,10 @@ struct ClearChildSubscribers;
 
 #[async_trait::async_trait]
 impl Handler<ClearChildSubscribers> for SubscriberParent {
-    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: ClearChildSubscribers) {
-        self.children_subscribers = Vec::new();
+    async fn handle(&mut self, ctx: &mut Context<Self>, _msg: ClearChildSubscribers) {
+        for child in ctx.children() {
+            let _ = child.stop(None);
+        }
     }
 }
 
@@ -1,5 +1,5 @@
 use std::time::{Duration, Instant};
-use xactor::{message, Actor, Context, Handler};
+use xactor::{message, Actor, Context, Handler, StopReason};
 
 #[derive(Debug)]
 pub struct PingTimer {
@@ -23,7 +23,7 @@ impl Actor for PingTimer {
     }
 
     /// Called after an actor is stopped.
-    async fn stopped(&mut self, _: &mut Context<Self>) {
+    async fn stopped(&mut self, _: &mut Context<Self>, _: &StopReason) {
         println!("PingTimer:: stopped()");
     }
 }
@@ -80,8 +80,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
 
     let _ = futures::join!(supervisor_task, send_halt);
-    // run this to see that the interval is not properly stopped if the ctx is stopped
-    // futures::join!(supervisor_task, send_panic); // there is no panic recovery
+    // Sending `Panic` instead of `Halt` does not stop the actor: the panic is caught, `stopped`
+    // is called and the supervisor restarts the actor with a new interval
 
     Ok(())
 }
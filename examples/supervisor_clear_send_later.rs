@@ -1,5 +1,5 @@
 use std::time::Duration;
-use xactor::{message, Actor, Context, Handler};
+use xactor::{message, Actor, Context, Handler, StopReason};
 
 #[derive(Debug, Default)]
 pub struct PingLater;
@@ -12,7 +12,7 @@ impl Actor for PingLater {
         Ok(())
     }
     /// Called after an actor is stopped.
-    async fn stopped(&mut self, _: &mut Context<Self>) {
+    async fn stopped(&mut self, _: &mut Context<Self>, _: &StopReason) {
         println!("PingLater:: stopped()");
     }
 }
@@ -1,21 +1,32 @@
-use crate::addr::ActorEvent;
+use crate::addr::{ActorEvent, ExecFn};
+use crate::mailbox::{MailboxConfig, MailboxReceiver, MailboxSender, Priority};
 use crate::runtime::spawn;
-use crate::{Addr, Context};
+use crate::{error, ActorId, ActorSystem, Addr, Context};
 use crate::error::Result;
-use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender};
 use futures::channel::oneshot;
+use futures::future::Either;
 use futures::{FutureExt, StreamExt};
+use std::any::Any;
+use std::fmt;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
 
 /// Represents a message that can be handled by the actor.
 pub trait Message: 'static + Send {
     /// The return value type of the message
     /// This type can be set to () if the message does not return a value, or if it is a notification message
     type Result: 'static + Send;
+
+    /// The priority of the message in the mailbox of the actor, `Priority::Normal` by default.
+    const PRIORITY: Priority = Priority::Normal;
 }
 
 /// Describes how to handle messages of a specific type.
 /// Implementing Handler is a general way to handle incoming messages.
 /// The type T is a message which can be handled by the actor.
+///
+/// If a handler panics, the actor is stopped with `StopReason::Panicked` and `Actor::stopped`
+/// is still called, a `Supervisor` restarts it like any other failure.
 #[async_trait::async_trait]
 pub trait Handler<T: Message>: Actor
 where
@@ -46,6 +57,105 @@ pub trait StreamHandler<T: 'static>: Actor {
     }
 }
 
+/// The reason an actor stopped.
+///
+/// It is passed to `Actor::stopped` and returned by `Addr::wait_for_stop`.
+///
+/// # Examples
+///
+/// ```rust
+/// use xactor::*;
+///
+/// #[message]
+/// struct Crash;
+///
+/// struct MyActor;
+///
+/// impl Actor for MyActor {}
+///
+/// #[async_trait::async_trait]
+/// impl Handler<Crash> for MyActor {
+///     async fn handle(&mut self, ctx: &mut Context<Self>, _msg: Crash) {
+///         ctx.stop(Some(error::Error::msg("crashed")));
+///     }
+/// }
+///
+/// #[xactor::main]
+/// async fn main() -> Result<()> {
+///     let addr = MyActor.start().await?;
+///     addr.send(Crash)?;
+///     let reason = addr.wait_for_stop().await;
+///     assert!(reason.is_failure());
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum StopReason {
+    /// The actor was stopped without an error, by `Context::stop(None)` or `Addr::stop(None)`.
+    Normal,
+
+    /// The actor was stopped with an error.
+    Failed(Arc<error::Error>),
+
+    /// All references to the address of the actor were dropped.
+    AddressesDropped,
+
+    /// The actor was shut down by its supervisor.
+    Shutdown,
+
+    /// A handler panicked, with the panic message.
+    Panicked(String),
+
+    /// The supervisor gave up restarting the actor, see `Supervisor::with_max_restarts`.
+    RestartLimitExceeded,
+
+    /// A linked actor stopped because of a failure, see `Context::link`.
+    LinkedExit {
+        /// The id of the linked actor.
+        actor_id: ActorId,
+
+        /// The reason the linked actor stopped.
+        reason: Box<StopReason>,
+    },
+}
+
+impl StopReason {
+    pub(crate) fn from_error(err: Option<error::Error>) -> Self {
+        match err {
+            Some(err) => StopReason::Failed(Arc::new(err)),
+            None => StopReason::Normal,
+        }
+    }
+
+    /// Returns `true` if the actor stopped because of a failure.
+    pub fn is_failure(&self) -> bool {
+        matches!(
+            self,
+            StopReason::Failed(_)
+                | StopReason::Panicked(_)
+                | StopReason::RestartLimitExceeded
+                | StopReason::LinkedExit { .. }
+        )
+    }
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Normal => f.write_str("Stopped"),
+            StopReason::Failed(err) => write!(f, "Failed: {}", err),
+            StopReason::AddressesDropped => f.write_str("All addresses dropped"),
+            StopReason::Shutdown => f.write_str("Shutdown"),
+            StopReason::Panicked(msg) => write!(f, "Panicked: {}", msg),
+            StopReason::RestartLimitExceeded => f.write_str("Restart limit exceeded"),
+            StopReason::LinkedExit { actor_id, reason } => {
+                write!(f, "Linked actor {} exited: {}", actor_id, reason)
+            }
+        }
+    }
+}
+
 /// Actors are objects which encapsulate state and behavior.
 /// Actors run within a specific execution context `Context<A>`.
 /// The context object is available only during execution.
@@ -63,8 +173,19 @@ pub trait Actor: Sized + Send + 'static {
         Ok(())
     }
 
-    /// Called after an actor is stopped.
-    async fn stopped(&mut self, ctx: &mut Context<Self>) {}
+    /// Called after an actor is stopped, with the reason it stopped.
+    async fn stopped(&mut self, ctx: &mut Context<Self>, reason: &StopReason) {}
+
+    /// Called on a stopped instance before a `Supervisor` replaces it with a new instance, with
+    /// the reason it stopped.
+    ///
+    /// Use `Context::set_restart_state` to hand a snapshot of the state to the new instance.
+    async fn pre_restart(&mut self, ctx: &mut Context<Self>, reason: &StopReason) {}
+
+    /// Called on the new instance created by a `Supervisor` when restarting, before `started`.
+    ///
+    /// Use `Context::take_restart_state` to get the snapshot handed over by the previous instance.
+    async fn post_restart(&mut self, ctx: &mut Context<Self>) {}
 
     /// Construct and start a new actor, returning its address.
     ///
@@ -111,20 +232,31 @@ pub trait Actor: Sized + Send + 'static {
     async fn start(self) -> Result<Addr<Self>> {
         ActorManager::new().start_actor(self).await
     }
+
+    /// Start a new actor with the specified mailbox configuration, returning its address.
+    ///
+    /// See `MailboxConfig` for the details.
+    async fn start_with_mailbox(self, mailbox: MailboxConfig) -> Result<Addr<Self>> {
+        ActorManager::with_mailbox(mailbox).start_actor(self).await
+    }
 }
 
 pub(crate) struct ActorManager<A: Actor> {
     ctx: Context<A>,
-    tx: std::sync::Arc<UnboundedSender<ActorEvent<A>>>,
-    rx: UnboundedReceiver<ActorEvent<A>>,
-    tx_exit: oneshot::Sender<()>,
+    tx: std::sync::Arc<MailboxSender<A>>,
+    rx: MailboxReceiver<A>,
+    tx_exit: oneshot::Sender<StopReason>,
 }
 
 impl<A: Actor> ActorManager<A> {
     pub(crate) fn new() -> Self {
+        Self::with_mailbox(MailboxConfig::default())
+    }
+
+    pub(crate) fn with_mailbox(mailbox: MailboxConfig) -> Self {
         let (tx_exit, rx_exit) = oneshot::channel();
         let rx_exit = rx_exit.shared();
-        let (ctx, rx, tx) = Context::new(Some(rx_exit));
+        let (ctx, rx, tx) = Context::new(Some(rx_exit), mailbox);
         Self {
             ctx,
             rx,
@@ -133,6 +265,18 @@ impl<A: Actor> ActorManager<A> {
         }
     }
 
+    /// Mark the actor as a service, services are shut down after all other actors.
+    pub(crate) fn service(mut self) -> Self {
+        self.ctx.service = true;
+        self
+    }
+
+    /// Start the actor in `system` instead of the global actor system.
+    pub(crate) fn in_system(mut self, system: ActorSystem) -> Self {
+        self.ctx.system = system;
+        self
+    }
+
     pub(crate) fn address(&self) -> Addr<A> {
         self.ctx.address()
     }
@@ -148,29 +292,30 @@ impl<A: Actor> ActorManager<A> {
         let rx_exit = ctx.rx_exit.clone();
         let actor_id = ctx.actor_id();
 
+        // Registered first, so that the actor can register names in `started`
+        let system = ctx.system.clone();
+        system.register(&ctx, &tx);
+
         // Call started
-        actor.started(&mut ctx).await?;
+        if let Err(err) = actor.started(&mut ctx).await {
+            // Remove the actor and the names registered in `started`
+            system.unregister(actor_id);
+            return Err(err);
+        }
 
         spawn({
             async move {
-                while let Some(event) = rx.next().await {
-                    match event {
-                        ActorEvent::Exec(f) => f(&mut actor, &mut ctx).await,
-                        ActorEvent::Stop(_err) => break,
-                        ActorEvent::RemoveStream(id) => {
-                            if ctx.streams.contains(id) {
-                                ctx.streams.remove(id);
-                            }
-                        }
-                    }
-                }
+                let reason = run_event_loop(&mut actor, &mut ctx, &mut rx).await;
 
-                actor.stopped(&mut ctx).await;
+                ctx.stop_children().await;
+                actor.stopped(&mut ctx, &reason).await;
 
-                ctx.abort_streams();
-                ctx.abort_intervals();
+                ctx.abort_tasks();
 
-                tx_exit.send(()).ok();
+                // Close the mailbox before notifying, so that sending to a stopped actor fails
+                drop(rx);
+                system.unregister(actor_id);
+                tx_exit.send(reason).ok();
             }
         });
 
@@ -181,3 +326,67 @@ impl<A: Actor> ActorManager<A> {
         })
     }
 }
+
+/// Handle the events of the mailbox until the actor is stopped, returning the reason.
+pub(crate) async fn run_event_loop<A: Actor>(
+    actor: &mut A,
+    ctx: &mut Context<A>,
+    rx: &mut MailboxReceiver<A>,
+) -> StopReason {
+    loop {
+        // Futures passed to `Context::wait` hold back all events but stop requests until they
+        // have completed
+        while let Some(f) = ctx.take_wait() {
+            let wait = exec(f, actor, ctx);
+            futures::pin_mut!(wait);
+            let stop = futures::future::poll_fn(|cx| rx.poll_stop(cx));
+            match futures::future::select(wait, stop).await {
+                Either::Left((Ok(()), _)) => {}
+                Either::Left((Err(reason), _)) | Either::Right((reason, _)) => return reason,
+            }
+        }
+
+        let event = match rx.next().await {
+            Some(event) => event,
+            None => break,
+        };
+        match event {
+            ActorEvent::Exec(f) => {
+                if let Err(reason) = exec(f, actor, ctx).await {
+                    return reason;
+                }
+            }
+            ActorEvent::Stop(reason) => return reason,
+            ActorEvent::RemoveStream(id) => {
+                if ctx.streams.contains(id) {
+                    ctx.streams.remove(id);
+                    ctx.update_stats();
+                }
+            }
+            ActorEvent::RemoveTimer(timer) => ctx.remove_timer(&timer),
+        }
+    }
+    StopReason::AddressesDropped
+}
+
+async fn exec<A: Actor>(
+    f: ExecFn<A>,
+    actor: &mut A,
+    ctx: &mut Context<A>,
+) -> Result<(), StopReason> {
+    // The actor is stopped after a panic, so it is never observed in a broken state
+    match AssertUnwindSafe(f(actor, ctx)).catch_unwind().await {
+        Ok(()) => Ok(()),
+        Err(payload) => Err(StopReason::Panicked(panic_message(payload))),
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(msg) => *msg,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(msg) => msg.to_string(),
+            Err(_) => "Box<dyn Any>".to_string(),
+        },
+    }
+}
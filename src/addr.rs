@@ -1,20 +1,93 @@
-use crate::{Actor, ActorId, Caller, Context, Error, Handler, Message, Result, Sender};
-use futures::channel::{mpsc, oneshot};
+use crate::mailbox::MailboxSender;
+use crate::runtime::timeout;
+use crate::timer::TimerShared;
+use crate::{
+    error, Actor, ActorId, Caller, Context, Error, Handler, Message, Result, Sender, StopReason,
+};
+use futures::channel::oneshot;
 use futures::future::Shared;
-use futures::Future;
+use futures::{Future, FutureExt};
+use std::any::Any;
+use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
 
-type ExecFuture<'a> = Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
+pub(crate) type ExecFuture<'a> = Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
 
 pub(crate) type ExecFn<A> =
     Box<dyn for<'a> FnOnce(&'a mut A, &'a mut Context<A>) -> ExecFuture<'a> + Send + 'static>;
 
+pub(crate) type ExitReceiver = Shared<oneshot::Receiver<StopReason>>;
+
 pub(crate) enum ActorEvent<A> {
     Exec(ExecFn<A>),
-    Stop(Option<Error>),
+    Stop(StopReason),
     RemoveStream(usize),
+    RemoveTimer(Arc<TimerShared>),
+}
+
+/// Send a message `msg` to the actor and wait for the return value.
+///
+/// If `timeout` is specified and elapses, the handler is not run if the message is still in the
+/// mailbox.
+pub(crate) async fn call<A, T>(
+    tx: &MailboxSender<A>,
+    msg: T,
+    timeout_dur: Option<Duration>,
+) -> Result<T::Result, Error>
+where
+    A: Handler<T>,
+    T: Message,
+{
+    let deadline = timeout_dur.map(|dur| Instant::now() + dur);
+    let (res_tx, res_rx) = oneshot::channel();
+    let handled = Arc::new(AtomicBool::new(false));
+    let handling = handled.clone();
+    let fut = async move {
+        tx.send(
+            T::PRIORITY,
+            ActorEvent::Exec(Box::new(move |actor, ctx| {
+                Box::pin(async move {
+                    if let Some(deadline) = deadline {
+                        if Instant::now() >= deadline {
+                            return;
+                        }
+                    }
+                    handling.store(true, Ordering::Relaxed);
+                    let res = Handler::handle(actor, ctx, msg).await;
+                    let _ = res_tx.send(res);
+                })
+            })),
+        )
+        .await?;
+        res_rx.await.map_err(|_| {
+            // The result is lost if the handler panicked, or if the message was discarded by the
+            // overflow policy or when the actor stopped
+            if handled.load(Ordering::Relaxed) || tx.is_closed() {
+                Error::ActorStopped
+            } else {
+                Error::Canceled
+            }
+        })
+    };
+
+    match timeout_dur {
+        Some(dur) => match timeout(dur, fut).await {
+            Ok(res) => res,
+            Err(_) => Err(Error::Timeout),
+        },
+        None => fut.await,
+    }
+}
+
+/// Wait for the exit signal of an actor, returning the reason it stopped.
+pub(crate) async fn wait_exit(rx_exit: ExitReceiver) -> StopReason {
+    rx_exit.await.unwrap_or_else(|_| {
+        StopReason::Failed(Arc::new(error::Error::msg("Actor terminated unexpectedly")))
+    })
 }
 
 /// The address of an actor.
@@ -23,8 +96,8 @@ pub(crate) enum ActorEvent<A> {
 /// You can use `Clone` trait to create multiple copies of `Addr<A>`.
 pub struct Addr<A> {
     pub(crate) actor_id: ActorId,
-    pub(crate) tx: Arc<mpsc::UnboundedSender<ActorEvent<A>>>,
-    pub(crate) rx_exit: Option<Shared<oneshot::Receiver<()>>>,
+    pub(crate) tx: Arc<MailboxSender<A>>,
+    pub(crate) rx_exit: Option<ExitReceiver>,
 }
 
 impl<A> Clone for Addr<A> {
@@ -65,43 +138,119 @@ impl<A: Actor> Addr<A> {
         self.actor_id
     }
 
+    /// Returns `true` if the actor has stopped, or failed to start.
+    pub(crate) fn is_stopped(&self) -> bool {
+        self.rx_exit
+            .as_ref()
+            .map_or(false, |rx_exit| rx_exit.clone().now_or_never().is_some())
+    }
+
     /// Stop the actor.
-    pub fn stop(&mut self, err: Option<Error>) -> Result<()> {
-        mpsc::UnboundedSender::clone(&*self.tx).start_send(ActorEvent::Stop(err))?;
-        Ok(())
+    pub fn stop(&mut self, err: Option<error::Error>) -> Result<(), Error> {
+        self.tx
+            .send_control(ActorEvent::Stop(StopReason::from_error(err)))
     }
 
     /// Send a message `msg` to the actor and wait for the return value.
-    pub async fn call<T: Message>(&self, msg: T) -> Result<T::Result>
+    ///
+    /// If the mailbox of the actor is bounded and full, wait for free space first. Returns
+    /// `Error::ActorStopped` if the actor stops before returning a value, including when the
+    /// handler panics.
+    pub async fn call<T: Message>(&self, msg: T) -> Result<T::Result, Error>
     where
         A: Handler<T>,
     {
-        let (tx, rx) = oneshot::channel();
-        mpsc::UnboundedSender::clone(&*self.tx).start_send(ActorEvent::Exec(Box::new(
-            move |actor, ctx| {
-                Box::pin(async move {
-                    let res = Handler::handle(actor, ctx, msg).await;
-                    let _ = tx.send(res);
-                })
-            },
-        )))?;
+        call(&self.tx, msg, None).await
+    }
 
-        Ok(rx.await?)
+    /// Send a message `msg` to the actor and wait for the return value, at most for `timeout`.
+    ///
+    /// Returns `Error::Timeout` if the time elapses, the handler is not run at all if the message
+    /// is dequeued after that.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use xactor::*;
+    /// use std::time::Duration;
+    ///
+    /// #[message]
+    /// struct Hang;
+    ///
+    /// struct MyActor;
+    ///
+    /// impl Actor for MyActor {}
+    ///
+    /// #[async_trait::async_trait]
+    /// impl Handler<Hang> for MyActor {
+    ///     async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Hang) {
+    ///         sleep(Duration::from_secs(1)).await;
+    ///     }
+    /// }
+    ///
+    /// #[xactor::main]
+    /// async fn main() -> Result<()> {
+    ///     let addr = MyActor.start().await?;
+    ///     let res = addr.call_timeout(Hang, Duration::from_millis(100)).await;
+    ///     assert!(matches!(res, Err(Error::Timeout)));
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn call_timeout<T: Message>(
+        &self,
+        msg: T,
+        timeout: Duration,
+    ) -> Result<T::Result, Error>
+    where
+        A: Handler<T>,
+    {
+        call(&self.tx, msg, Some(timeout)).await
     }
 
     /// Send a message `msg` to the actor without waiting for the return value.
-    pub fn send<T: Message<Result = ()>>(&self, msg: T) -> Result<()>
+    ///
+    /// This is the same as `try_send`, use `send_async` to wait for free space in a bounded mailbox.
+    pub fn send<T: Message<Result = ()>>(&self, msg: T) -> Result<(), Error>
     where
         A: Handler<T>,
     {
-        mpsc::UnboundedSender::clone(&*self.tx).start_send(ActorEvent::Exec(Box::new(
-            move |actor, ctx| {
+        self.try_send(msg)
+    }
+
+    /// Try to send a message `msg` to the actor without waiting for the return value.
+    ///
+    /// Returns `Error::MailboxFull` if the mailbox of the actor is bounded and full.
+    pub fn try_send<T: Message<Result = ()>>(&self, msg: T) -> Result<(), Error>
+    where
+        A: Handler<T>,
+    {
+        self.tx.try_send(
+            T::PRIORITY,
+            ActorEvent::Exec(Box::new(move |actor, ctx| {
                 Box::pin(async move {
                     Handler::handle(actor, ctx, msg).await;
                 })
-            },
-        )))?;
-        Ok(())
+            })),
+        )
+    }
+
+    /// Send a message `msg` to the actor without waiting for the return value.
+    ///
+    /// If the mailbox of the actor is bounded and full, wait for free space first.
+    pub async fn send_async<T: Message<Result = ()>>(&self, msg: T) -> Result<(), Error>
+    where
+        A: Handler<T>,
+    {
+        self.tx
+            .send(
+                T::PRIORITY,
+                ActorEvent::Exec(Box::new(move |actor, ctx| {
+                    Box::pin(async move {
+                        Handler::handle(actor, ctx, msg).await;
+                    })
+                })),
+            )
+            .await
     }
 
     /// Create a `Caller<T>` for a specific message type
@@ -112,25 +261,13 @@ impl<A: Actor> Addr<A> {
         let weak_tx = Arc::downgrade(&self.tx);
 
         Caller {
-            actor_id: self.actor_id.clone(),
-            caller_fn: Mutex::new(Box::new(move |msg| {
+            actor_id: self.actor_id,
+            caller_fn: Mutex::new(Box::new(move |msg, timeout| {
                 let weak_tx_option = weak_tx.upgrade();
                 Box::pin(async move {
                     match weak_tx_option {
-                        Some(tx) => {
-                            let (oneshot_tx, oneshot_rx) = oneshot::channel();
-
-                            mpsc::UnboundedSender::clone(&tx).start_send(ActorEvent::Exec(
-                                Box::new(move |actor, ctx| {
-                                    Box::pin(async move {
-                                        let res = Handler::handle(&mut *actor, ctx, msg).await;
-                                        let _ = oneshot_tx.send(res);
-                                    })
-                                }),
-                            ))?;
-                            Ok(oneshot_rx.await?)
-                        }
-                        None => Err(crate::error::anyhow!("Actor Dropped")),
+                        Some(tx) => call(&tx, msg, timeout).await,
+                        None => Err(Error::ActorStopped),
                     }
                 })
             })),
@@ -144,37 +281,124 @@ impl<A: Actor> Addr<A> {
     {
         let weak_tx = Arc::downgrade(&self.tx);
         Sender {
-            actor_id: self.actor_id.clone(),
+            actor_id: self.actor_id,
             sender_fn: Box::new(move |msg| match weak_tx.upgrade() {
-                Some(tx) => {
-                    mpsc::UnboundedSender::clone(&tx).start_send(ActorEvent::Exec(Box::new(
-                        move |actor, ctx| {
-                            Box::pin(async move {
-                                Handler::handle(&mut *actor, ctx, msg).await;
-                            })
-                        },
-                    )))?;
-                    Ok(())
-                }
+                Some(tx) => tx.try_send(
+                    T::PRIORITY,
+                    ActorEvent::Exec(Box::new(move |actor, ctx| {
+                        Box::pin(async move {
+                            Handler::handle(&mut *actor, ctx, msg).await;
+                        })
+                    })),
+                ),
                 None => Ok(()),
             }),
         }
     }
 
     /// Wait for an actor to finish, and if the actor has finished, the function returns immediately.
-    pub async fn wait_for_stop(self) {
+    ///
+    /// Returns the reason the actor stopped.
+    pub async fn wait_for_stop(self) -> StopReason {
+        if let Some(rx_exit) = self.rx_exit {
+            wait_exit(rx_exit).await
+        } else {
+            futures::future::pending().await
+        }
+    }
+}
+
+/// A type-erased address of an actor.
+///
+/// It keeps the actor alive like `Addr<A>`, use `downcast` to get the typed address back.
+#[derive(Clone)]
+pub struct AnyAddr {
+    pub(crate) actor_id: ActorId,
+    type_name: &'static str,
+    addr: Arc<dyn Any + Send + Sync>,
+    stop_fn: fn(&(dyn Any + Send + Sync), StopReason) -> Result<(), Error>,
+    pub(crate) rx_exit: Option<ExitReceiver>,
+}
+
+fn stop_any<A: Actor>(addr: &(dyn Any + Send + Sync), reason: StopReason) -> Result<(), Error> {
+    match addr.downcast_ref::<Addr<A>>() {
+        Some(addr) => addr.tx.send_control(ActorEvent::Stop(reason)),
+        None => Err(Error::ActorStopped),
+    }
+}
+
+impl<A: Actor> From<Addr<A>> for AnyAddr {
+    fn from(addr: Addr<A>) -> Self {
+        Self {
+            actor_id: addr.actor_id,
+            type_name: std::any::type_name::<A>(),
+            rx_exit: addr.rx_exit.clone(),
+            addr: Arc::new(addr),
+            stop_fn: stop_any::<A>,
+        }
+    }
+}
+
+impl AnyAddr {
+    /// Returns the id of the actor.
+    pub fn actor_id(&self) -> ActorId {
+        self.actor_id
+    }
+
+    /// Returns the type name of the actor.
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    /// Returns the typed address if the actor is of type `A`.
+    pub fn downcast<A: Actor>(&self) -> Option<Addr<A>> {
+        self.addr.downcast_ref::<Addr<A>>().cloned()
+    }
+
+    /// Stop the actor.
+    pub fn stop(&self, err: Option<error::Error>) -> Result<(), Error> {
+        self.stop_with_reason(StopReason::from_error(err))
+    }
+
+    pub(crate) fn stop_with_reason(&self, reason: StopReason) -> Result<(), Error> {
+        (self.stop_fn)(&*self.addr, reason)
+    }
+
+    /// Wait for an actor to finish, returning the reason it stopped.
+    pub async fn wait_for_stop(self) -> StopReason {
         if let Some(rx_exit) = self.rx_exit {
-            rx_exit.await.ok();
+            wait_exit(rx_exit).await
         } else {
-            futures::future::pending::<()>().await;
+            futures::future::pending().await
         }
     }
 }
 
+impl fmt::Debug for AnyAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AnyAddr")
+            .field("actor_id", &self.actor_id)
+            .field("type_name", &self.type_name)
+            .finish()
+    }
+}
+
+impl PartialEq for AnyAddr {
+    fn eq(&self, other: &Self) -> bool {
+        self.actor_id == other.actor_id
+    }
+}
+
+impl Hash for AnyAddr {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.actor_id.hash(state)
+    }
+}
+
 pub struct WeakAddr<A> {
     pub(crate) actor_id: ActorId,
-    pub(crate) tx: Weak<mpsc::UnboundedSender<ActorEvent<A>>>,
-    pub(crate) rx_exit: Option<Shared<oneshot::Receiver<()>>>,
+    pub(crate) tx: Weak<MailboxSender<A>>,
+    pub(crate) rx_exit: Option<ExitReceiver>,
 }
 
 impl<A> PartialEq for WeakAddr<A> {
@@ -217,4 +441,21 @@ impl<A: Actor> WeakAddr<A> {
     pub fn actor_id(&self) -> ActorId {
         self.actor_id
     }
+
+    /// Send a message `msg` to the actor and wait for the return value, at most for `timeout`.
+    ///
+    /// See `Addr::call_timeout` for the details.
+    pub async fn call_timeout<T: Message>(
+        &self,
+        msg: T,
+        timeout: Duration,
+    ) -> Result<T::Result, Error>
+    where
+        A: Handler<T>,
+    {
+        match self.tx.upgrade() {
+            Some(tx) => call(&tx, msg, Some(timeout)).await,
+            None => Err(Error::ActorStopped),
+        }
+    }
 }
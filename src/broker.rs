@@ -1,4 +1,4 @@
-use crate::{Actor, Addr, Context, Handler, Message, Result, Sender, Service};
+use crate::{Actor, Addr, Context, Error, Handler, Message, Result, Sender, Service};
 use fnv::FnvHasher;
 use std::any::Any;
 use std::collections::HashMap;
@@ -130,7 +130,7 @@ impl<T: Message<Result = ()> + Clone> Handler<Publish<T>> for Broker<T> {
 
 impl<T: Message<Result = ()> + Clone> Addr<Broker<T>> {
     /// Publishes a message of the specified type.
-    pub fn publish(&mut self, msg: T) -> Result<()> {
+    pub fn publish(&mut self, msg: T) -> Result<(), Error> {
         self.send(Publish(msg))
     }
 }
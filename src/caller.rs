@@ -1,28 +1,38 @@
-use crate::{ActorId, Message, Result};
+use crate::{ActorId, Error, Message};
 use std::future::Future;
 use std::hash::{Hash, Hasher};
 use std::pin::Pin;
 use std::sync::Mutex;
+use std::time::Duration;
 
 pub(crate) type CallerFuture<T> =
-    Pin<Box<dyn Future<Output = Result<<T as Message>::Result>> + Send + 'static>>;
+    Pin<Box<dyn Future<Output = Result<<T as Message>::Result, Error>> + Send + 'static>>;
 
-pub(crate) type CallerFn<T> = Box<dyn Fn(T) -> CallerFuture<T> + Send + 'static>;
+pub(crate) type CallerFn<T> = Box<dyn Fn(T, Option<Duration>) -> CallerFuture<T> + Send + 'static>;
 
-pub(crate) type SenderFn<T> = Box<dyn Fn(T) -> Result<()> + 'static + Send>;
+pub(crate) type SenderFn<T> = Box<dyn Fn(T) -> Result<(), Error> + 'static + Send>;
 
 /// Caller of a specific message type
 ///
 /// Like `Sender<T>, Caller has a weak reference to the recipient of the message type, and so will not prevent an actor from stopping if all Addr's have been dropped elsewhere.
-
 pub struct Caller<T: Message> {
     pub actor_id: ActorId,
     pub(crate) caller_fn: Mutex<CallerFn<T>>,
 }
 
 impl<T: Message> Caller<T> {
+    /// Send a message `msg` to the actor and wait for the return value.
+    ///
+    /// If the mailbox of the actor is bounded and full, wait for free space first.
     pub fn call(&self, msg: T) -> CallerFuture<T> {
-        (self.caller_fn.lock().unwrap())(msg)
+        (self.caller_fn.lock().unwrap())(msg, None)
+    }
+
+    /// Send a message `msg` to the actor and wait for the return value, at most for `timeout`.
+    ///
+    /// See `Addr::call_timeout` for the details.
+    pub fn call_timeout(&self, msg: T, timeout: Duration) -> CallerFuture<T> {
+        (self.caller_fn.lock().unwrap())(msg, Some(timeout))
     }
 }
 
@@ -42,14 +52,16 @@ impl<T: Message<Result = ()>> Hash for Caller<T> {
 ///
 /// Like `Caller<T>, Sender has a weak reference to the recipient of the message type, and so will not prevent an actor from stopping if all Addr's have been dropped elsewhere.
 /// This allows it to be used in `send_later` `send_interval` actor functions, and not keep the actor alive indefinitely even after all references to it have been dropped (unless `ctx.stop()` is called from within)
-
 pub struct Sender<T: Message> {
     pub actor_id: ActorId,
     pub(crate) sender_fn: SenderFn<T>,
 }
 
 impl<T: Message<Result = ()>> Sender<T> {
-    pub fn send(&self, msg: T) -> Result<()> {
+    /// Send a message `msg` to the actor without waiting for the return value.
+    ///
+    /// Returns `Error::MailboxFull` if the mailbox of the actor is bounded and full.
+    pub fn send(&self, msg: T) -> Result<(), Error> {
         (self.sender_fn)(msg)
     }
 }
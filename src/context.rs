@@ -1,33 +1,102 @@
-use crate::addr::ActorEvent;
+use crate::actor::ActorManager;
+use crate::addr::{wait_exit, ActorEvent, ExecFn, ExecFuture, ExitReceiver};
 use crate::broker::{Subscribe, Unsubscribe};
-use crate::runtime::{sleep, spawn};
-use crate::{ActorId, Addr, Broker, Error, Handler, Message, Result, Service, StreamHandler};
-use futures::channel::{mpsc, oneshot};
-use futures::future::{AbortHandle, Abortable, Shared};
-use futures::{Stream, StreamExt};
+use crate::mailbox::{
+    self, MailboxConfig, MailboxReceiver, MailboxSender, MailboxStatus, Priority,
+};
+use crate::runtime::spawn;
+use crate::timer::{timer, TimerShared};
+use crate::{
+    error, Actor, ActorId, ActorSystem, Addr, AnyAddr, Broker, Error, Handler, Message, Result,
+    StopReason, StreamHandler, TimerHandle, WeakAddr,
+};
+use fnv::FnvHasher;
+use futures::future::{AbortHandle, Abortable};
+use futures::{Future, Stream, StreamExt};
 use once_cell::sync::OnceCell;
 use slab::Slab;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, Weak};
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{BuildHasherDefault, Hash};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
 use std::time::Duration;
 
+/// The message delivered to an actor when an actor it watches stops.
+///
+/// See `Context::watch`.
+#[derive(Debug, Clone)]
+pub struct Terminated {
+    /// The id of the stopped actor.
+    pub actor_id: ActorId,
+
+    /// The reason the actor stopped.
+    pub reason: StopReason,
+}
+
+impl Message for Terminated {
+    type Result = ();
+}
+
+/// The message delivered to an actor that traps exits when a linked actor stops.
+///
+/// See `Context::link` and `Context::trap_exit`.
+#[derive(Debug, Clone)]
+pub struct Exit {
+    /// The id of the linked actor.
+    pub actor_id: ActorId,
+
+    /// The reason the linked actor stopped.
+    pub reason: StopReason,
+}
+
+impl Message for Exit {
+    type Result = ();
+}
+
+type TrapExitFn<A> = for<'a> fn(&'a mut A, &'a mut Context<A>, Exit) -> ExecFuture<'a>;
+
+fn trap_exit<'a, A: Handler<Exit>>(
+    actor: &'a mut A,
+    ctx: &'a mut Context<A>,
+    msg: Exit,
+) -> ExecFuture<'a> {
+    Handler::handle(actor, ctx, msg)
+}
+
+/// Counters of a context, read by `ActorSystem::actors`.
+#[derive(Default)]
+pub(crate) struct ContextStats {
+    pub(crate) streams: AtomicUsize,
+    pub(crate) intervals: AtomicUsize,
+}
+
 ///An actor execution context.
 pub struct Context<A> {
     actor_id: ActorId,
-    tx: Weak<mpsc::UnboundedSender<ActorEvent<A>>>,
-    pub(crate) rx_exit: Option<Shared<oneshot::Receiver<()>>>,
+    pub(crate) tx: Weak<MailboxSender<A>>,
+    pub(crate) mailbox: MailboxStatus<A>,
+    pub(crate) stats: Arc<ContextStats>,
+    pub(crate) rx_exit: Option<ExitReceiver>,
     pub(crate) streams: Slab<AbortHandle>,
-    pub(crate) intervals: Slab<AbortHandle>,
+    pub(crate) intervals: Slab<Arc<TimerShared>>,
+    pub(crate) spawned: Slab<Arc<AbortHandle>>,
+    // In a mutex only to keep the context `Sync`, it is accessed through `&mut self`
+    waits: Mutex<VecDeque<ExecFn<A>>>,
+    pub(crate) watches: HashMap<ActorId, AbortHandle, BuildHasherDefault<FnvHasher>>,
+    pub(crate) links: HashMap<ActorId, AbortHandle, BuildHasherDefault<FnvHasher>>,
+    trap_exit: Option<TrapExitFn<A>>,
+    children: Vec<(AnyAddr, AbortHandle)>,
+    pub(crate) restart_state: Option<Box<dyn Any + Send + Sync>>,
+    pub(crate) system: ActorSystem,
+    pub(crate) service: bool,
 }
 
 impl<A> Context<A> {
     pub(crate) fn new(
-        rx_exit: Option<Shared<oneshot::Receiver<()>>>,
-    ) -> (
-        Self,
-        mpsc::UnboundedReceiver<ActorEvent<A>>,
-        Arc<mpsc::UnboundedSender<ActorEvent<A>>>,
-    ) {
+        rx_exit: Option<ExitReceiver>,
+        mailbox: MailboxConfig,
+    ) -> (Self, MailboxReceiver<A>, Arc<MailboxSender<A>>) {
         static ACTOR_ID: OnceCell<AtomicU64> = OnceCell::new();
 
         // Get an actor id
@@ -35,16 +104,27 @@ impl<A> Context<A> {
             .get_or_init(Default::default)
             .fetch_add(1, Ordering::Relaxed);
 
-        let (tx, rx) = mpsc::unbounded::<ActorEvent<A>>();
+        let (tx, rx) = mailbox::channel::<A>(mailbox);
         let tx = Arc::new(tx);
         let weak_tx = Arc::downgrade(&tx);
         (
             Self {
                 actor_id,
                 tx: weak_tx,
+                mailbox: rx.status(),
+                stats: Default::default(),
                 rx_exit,
                 streams: Default::default(),
                 intervals: Default::default(),
+                spawned: Default::default(),
+                waits: Default::default(),
+                watches: Default::default(),
+                links: Default::default(),
+                trap_exit: None,
+                children: Vec::new(),
+                restart_state: None,
+                system: ActorSystem::global(),
+                service: false,
             },
             rx,
             tx,
@@ -66,25 +146,503 @@ impl<A> Context<A> {
         self.actor_id
     }
 
+    /// Returns the actor system the actor belongs to.
+    pub fn system(&self) -> &ActorSystem {
+        &self.system
+    }
+
+    /// Register the actor under `key` in its actor system, see `ActorSystem::register_name`.
+    pub fn register_name<Q>(&self, key: &Q) -> Result<(), Error>
+    where
+        A: Actor,
+        Q: ?Sized + Hash + Eq + ToOwned,
+        Q::Owned: Hash + Eq + Send + 'static,
+    {
+        let addr = WeakAddr {
+            actor_id: self.actor_id,
+            tx: self.tx.clone(),
+            rx_exit: self.rx_exit.clone(),
+        };
+        self.system.insert_name(key, addr)
+    }
+
+    /// Returns the number of messages discarded by the overflow policy of the mailbox.
+    ///
+    /// See `OverflowPolicy` for the details.
+    pub fn dropped_messages(&self) -> u64 {
+        self.mailbox.dropped()
+    }
+
+    /// Hand a snapshot of the state of the actor over to the instance replacing it, called from
+    /// `Actor::pre_restart`.
+    ///
+    /// It is discarded if the actor is not restarted by a `Supervisor`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use xactor::*;
+    /// use std::collections::HashMap;
+    ///
+    /// #[message]
+    /// struct Insert(String, String);
+    ///
+    /// #[message(result = "Option<String>")]
+    /// struct Get(String);
+    ///
+    /// #[message]
+    /// struct Crash;
+    ///
+    /// #[derive(Default)]
+    /// struct MyActor {
+    ///     cache: HashMap<String, String>,
+    /// }
+    ///
+    /// #[async_trait::async_trait]
+    /// impl Actor for MyActor {
+    ///     async fn pre_restart(&mut self, ctx: &mut Context<Self>, _reason: &StopReason) {
+    ///         ctx.set_restart_state(std::mem::take(&mut self.cache));
+    ///     }
+    ///
+    ///     async fn post_restart(&mut self, ctx: &mut Context<Self>) {
+    ///         if let Some(cache) = ctx.take_restart_state() {
+    ///             self.cache = cache;
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// #[async_trait::async_trait]
+    /// impl Handler<Insert> for MyActor {
+    ///     async fn handle(&mut self, _ctx: &mut Context<Self>, msg: Insert) {
+    ///         self.cache.insert(msg.0, msg.1);
+    ///     }
+    /// }
+    ///
+    /// #[async_trait::async_trait]
+    /// impl Handler<Get> for MyActor {
+    ///     async fn handle(&mut self, _ctx: &mut Context<Self>, msg: Get) -> Option<String> {
+    ///         self.cache.get(&msg.0).cloned()
+    ///     }
+    /// }
+    ///
+    /// #[async_trait::async_trait]
+    /// impl Handler<Crash> for MyActor {
+    ///     async fn handle(&mut self, ctx: &mut Context<Self>, _msg: Crash) {
+    ///         ctx.stop(Some(error::Error::msg("crashed")));
+    ///     }
+    /// }
+    ///
+    /// #[xactor::main]
+    /// async fn main() -> Result<()> {
+    ///     let addr = Supervisor::start(MyActor::default).await?;
+    ///     addr.send(Insert("key".to_string(), "value".to_string()))?;
+    ///     addr.send(Crash)?;
+    ///
+    ///     // Handled by the restarted instance
+    ///     assert_eq!(addr.call(Get("key".to_string())).await?, Some("value".to_string()));
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn set_restart_state<T: Send + Sync + 'static>(&mut self, state: T) {
+        self.restart_state = Some(Box::new(state));
+    }
+
+    /// Take the snapshot handed over by the previous instance with `set_restart_state`, called
+    /// from `Actor::post_restart`.
+    ///
+    /// Returns `None` if there is no snapshot or it is not of type `T`.
+    pub fn take_restart_state<T: Send + Sync + 'static>(&mut self) -> Option<T> {
+        let state: Box<dyn Any> = self.restart_state.take()?;
+        match state.downcast() {
+            Ok(state) => Some(*state),
+            Err(_) => None,
+        }
+    }
+
     /// Stop the actor.
-    pub fn stop(&self, err: Option<Error>) {
+    pub fn stop(&self, err: Option<error::Error>) {
+        self.stop_with_reason(StopReason::from_error(err));
+    }
+
+    pub(crate) fn stop_with_reason(&self, reason: StopReason) {
         if let Some(tx) = self.tx.upgrade() {
-            mpsc::UnboundedSender::clone(&*tx)
-                .start_send(ActorEvent::Stop(err))
-                .ok();
+            tx.send_control(ActorEvent::Stop(reason)).ok();
         }
     }
 
     pub fn abort_intervals(&mut self) {
-        for handle in self.intervals.drain() {
-            handle.abort()
+        for timer in self.intervals.drain() {
+            timer.cancel()
         }
+        self.update_stats();
     }
 
     pub fn abort_streams(&mut self) {
         for handle in self.streams.drain() {
             handle.abort();
         }
+        self.update_stats();
+    }
+
+    pub(crate) fn update_stats(&self) {
+        self.stats
+            .streams
+            .store(self.streams.len(), Ordering::Relaxed);
+        self.stats
+            .intervals
+            .store(self.intervals.len(), Ordering::Relaxed);
+    }
+
+    pub fn abort_spawned(&mut self) {
+        for handle in self.spawned.drain() {
+            handle.abort();
+        }
+    }
+
+    pub fn abort_watches(&mut self) {
+        for (_, handle) in self.watches.drain() {
+            handle.abort();
+        }
+    }
+
+    pub fn abort_links(&mut self) {
+        for (_, handle) in self.links.drain() {
+            handle.abort();
+        }
+    }
+
+    /// Abort everything the actor instance has set up in the context, called when it stops.
+    pub(crate) fn abort_tasks(&mut self) {
+        self.abort_streams();
+        self.abort_intervals();
+        self.abort_spawned();
+        self.abort_watches();
+        self.abort_links();
+        self.waits.get_mut().unwrap().clear();
+        self.trap_exit = None;
+    }
+
+    /// Watch another actor, `Terminated` is delivered to this actor when it stops.
+    ///
+    /// If the watched actor has already stopped, `Terminated` is delivered immediately. Watching an
+    /// actor started by `Supervisor` has no effect, since it is restarted instead of stopping.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use xactor::*;
+    /// use futures::channel::oneshot;
+    ///
+    /// struct Worker;
+    ///
+    /// impl Actor for Worker {}
+    ///
+    /// #[message]
+    /// struct Watch(Addr<Worker>);
+    ///
+    /// struct Monitor(Option<oneshot::Sender<ActorId>>);
+    ///
+    /// impl Actor for Monitor {}
+    ///
+    /// #[async_trait::async_trait]
+    /// impl Handler<Watch> for Monitor {
+    ///     async fn handle(&mut self, ctx: &mut Context<Self>, msg: Watch) {
+    ///         ctx.watch(&msg.0);
+    ///     }
+    /// }
+    ///
+    /// #[async_trait::async_trait]
+    /// impl Handler<Terminated> for Monitor {
+    ///     async fn handle(&mut self, _ctx: &mut Context<Self>, msg: Terminated) {
+    ///         if let Some(tx) = self.0.take() {
+    ///             tx.send(msg.actor_id).ok();
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// #[xactor::main]
+    /// async fn main() -> Result<()> {
+    ///     let (tx, rx) = oneshot::channel();
+    ///     let monitor = Monitor(Some(tx)).start().await?;
+    ///     let mut worker = Worker.start().await?;
+    ///     monitor.call(Watch(worker.clone())).await?;
+    ///
+    ///     worker.stop(None)?;
+    ///     assert_eq!(rx.await?, worker.actor_id());
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn watch<B: Actor>(&mut self, addr: &Addr<B>)
+    where
+        A: Handler<Terminated>,
+    {
+        let rx_exit = match &addr.rx_exit {
+            Some(rx_exit) => rx_exit.clone(),
+            None => return,
+        };
+        let actor_id = addr.actor_id;
+        let tx = self.tx.clone();
+        let (handle, registration) = AbortHandle::new_pair();
+        if let Some(handle) = self.watches.insert(actor_id, handle) {
+            handle.abort();
+        }
+
+        spawn(Abortable::new(
+            async move {
+                let reason = wait_exit(rx_exit).await;
+                if let Some(tx) = tx.upgrade() {
+                    tx.send_control(ActorEvent::Exec(Box::new(move |actor, ctx| {
+                        Box::pin(async move {
+                            // The watch may have been canceled after the event was sent
+                            if ctx.watches.remove(&actor_id).is_some() {
+                                let msg = Terminated { actor_id, reason };
+                                Handler::handle(actor, ctx, msg).await;
+                            }
+                        })
+                    })))
+                    .ok();
+                }
+            },
+            registration,
+        ));
+    }
+
+    /// Stop watching an actor, `Terminated` will not be delivered for it.
+    pub fn unwatch<B>(&mut self, addr: &Addr<B>) {
+        if let Some(handle) = self.watches.remove(&addr.actor_id) {
+            handle.abort();
+        }
+    }
+
+    /// Link this actor with another actor.
+    ///
+    /// When either of them stops because of a failure, the other one is stopped with
+    /// `StopReason::LinkedExit`, unless it traps exits, see `trap_exit`. Stopping without a failure
+    /// does not affect the linked actor. Actors started by `Supervisor` are restarted instead of
+    /// stopping, so their failures are not propagated.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use xactor::*;
+    ///
+    /// #[message]
+    /// struct Crash;
+    ///
+    /// struct Worker;
+    ///
+    /// impl Actor for Worker {}
+    ///
+    /// #[async_trait::async_trait]
+    /// impl Handler<Crash> for Worker {
+    ///     async fn handle(&mut self, ctx: &mut Context<Self>, _msg: Crash) {
+    ///         ctx.stop(Some(error::Error::msg("crashed")));
+    ///     }
+    /// }
+    ///
+    /// struct Partner(Addr<Worker>);
+    ///
+    /// #[async_trait::async_trait]
+    /// impl Actor for Partner {
+    ///     async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+    ///         ctx.link(&self.0);
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// #[xactor::main]
+    /// async fn main() -> Result<()> {
+    ///     let worker = Worker.start().await?;
+    ///     let partner = Partner(worker.clone()).start().await?;
+    ///
+    ///     worker.send(Crash)?;
+    ///     let reason = partner.wait_for_stop().await;
+    ///     assert!(matches!(reason, StopReason::LinkedExit { .. }));
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn link<B: Actor>(&mut self, addr: &Addr<B>)
+    where
+        A: Actor,
+    {
+        if addr.actor_id == self.actor_id {
+            return;
+        }
+        if let Some(rx_exit) = &addr.rx_exit {
+            self.add_link(addr.actor_id, rx_exit.clone());
+        }
+        if let Some(rx_exit) = self.rx_exit.clone() {
+            let actor_id = self.actor_id;
+            addr.tx
+                .send_control(ActorEvent::Exec(Box::new(move |_, ctx| {
+                    ctx.add_link(actor_id, rx_exit);
+                    Box::pin(async {})
+                })))
+                .ok();
+        }
+    }
+
+    /// Start a child actor, returning its address.
+    ///
+    /// The child is stopped with `StopReason::Shutdown` when this actor stops, and this actor
+    /// waits for it before `Actor::stopped` is called. The child is kept alive by this actor even
+    /// when all other addresses are dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use xactor::*;
+    ///
+    /// struct Child;
+    ///
+    /// impl Actor for Child {}
+    ///
+    /// #[derive(Default)]
+    /// struct Parent(Option<Addr<Child>>);
+    ///
+    /// #[async_trait::async_trait]
+    /// impl Actor for Parent {
+    ///     async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+    ///         self.0 = Some(ctx.spawn_child(Child).await?);
+    ///         assert_eq!(ctx.children().count(), 1);
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// #[xactor::main]
+    /// async fn main() -> Result<()> {
+    ///     let mut parent = Parent::start_default().await?;
+    ///     let child = parent.call(GetChild).await?;
+    ///
+    ///     parent.stop(None)?;
+    ///     assert!(matches!(child.wait_for_stop().await, StopReason::Shutdown));
+    ///     Ok(())
+    /// }
+    /// # #[message(result = "Addr<Child>")]
+    /// # struct GetChild;
+    /// # #[async_trait::async_trait]
+    /// # impl Handler<GetChild> for Parent {
+    /// #     async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: GetChild) -> Addr<Child> {
+    /// #         self.0.clone().unwrap()
+    /// #     }
+    /// # }
+    /// ```
+    pub async fn spawn_child<B: Actor>(&mut self, child: B) -> Result<Addr<B>>
+    where
+        A: Actor,
+    {
+        let addr = ActorManager::new()
+            .in_system(self.system.clone())
+            .start_actor(child)
+            .await?;
+        let child = AnyAddr::from(addr.clone());
+        let actor_id = child.actor_id;
+        let (handle, registration) = AbortHandle::new_pair();
+        if let Some(rx_exit) = child.rx_exit.clone() {
+            let tx = self.tx.clone();
+            spawn(Abortable::new(
+                async move {
+                    wait_exit(rx_exit).await;
+                    if let Some(tx) = tx.upgrade() {
+                        tx.send_control(ActorEvent::Exec(Box::new(move |_, ctx| {
+                            ctx.children.retain(|(child, _)| child.actor_id != actor_id);
+                            Box::pin(async {})
+                        })))
+                        .ok();
+                    }
+                },
+                registration,
+            ));
+        }
+        self.children.push((child, handle));
+        Ok(addr)
+    }
+
+    /// Returns the running children of this actor, in the order they were started.
+    ///
+    /// See `spawn_child`.
+    pub fn children(&self) -> impl Iterator<Item = &AnyAddr> {
+        self.children.iter().map(|(child, _)| child)
+    }
+
+    /// Stop all children in the reverse order they were started, and wait for them to finish.
+    pub(crate) async fn stop_children(&mut self) {
+        let children = std::mem::take(&mut self.children);
+        let mut exits = Vec::with_capacity(children.len());
+        for (child, handle) in children.into_iter().rev() {
+            handle.abort();
+            child.stop_with_reason(StopReason::Shutdown).ok();
+            exits.push(child.wait_for_stop());
+        }
+        futures::future::join_all(exits).await;
+    }
+
+    /// Remove the link between this actor and another actor.
+    pub fn unlink<B: Actor>(&mut self, addr: &Addr<B>) {
+        if let Some(handle) = self.links.remove(&addr.actor_id) {
+            handle.abort();
+        }
+        let actor_id = self.actor_id;
+        addr.tx
+            .send_control(ActorEvent::Exec(Box::new(move |_, ctx| {
+                if let Some(handle) = ctx.links.remove(&actor_id) {
+                    handle.abort();
+                }
+                Box::pin(async {})
+            })))
+            .ok();
+    }
+
+    /// Trap exits of linked actors.
+    ///
+    /// When trapping exits, the actor is not stopped when a linked actor fails, an `Exit` message
+    /// is delivered instead whenever a linked actor stops for any reason.
+    pub fn trap_exit(&mut self, trap: bool)
+    where
+        A: Handler<Exit>,
+    {
+        self.trap_exit = if trap { Some(trap_exit::<A>) } else { None };
+    }
+
+    fn add_link(&mut self, actor_id: ActorId, rx_exit: ExitReceiver)
+    where
+        A: Actor,
+    {
+        let tx = self.tx.clone();
+        let (handle, registration) = AbortHandle::new_pair();
+        if let Some(handle) = self.links.insert(actor_id, handle) {
+            handle.abort();
+        }
+
+        spawn(Abortable::new(
+            async move {
+                let reason = wait_exit(rx_exit).await;
+                if let Some(tx) = tx.upgrade() {
+                    tx.send_control(ActorEvent::Exec(Box::new(move |actor, ctx| {
+                        Box::pin(async move {
+                            // The link may have been removed after the event was sent
+                            if ctx.links.remove(&actor_id).is_none() {
+                                return;
+                            }
+                            match ctx.trap_exit {
+                                Some(trap_exit) => {
+                                    trap_exit(actor, ctx, Exit { actor_id, reason }).await
+                                }
+                                None if reason.is_failure() => {
+                                    ctx.stop_with_reason(StopReason::LinkedExit {
+                                        actor_id,
+                                        reason: Box::new(reason),
+                                    })
+                                }
+                                None => {}
+                            }
+                        })
+                    })))
+                    .ok();
+                }
+            },
+            registration,
+        ));
     }
 
     /// Create a stream handler for the actor.
@@ -153,30 +711,37 @@ impl<A> Context<A> {
         let id = entry.key();
         let (handle, registration) = futures::future::AbortHandle::new_pair();
         entry.insert(handle);
+        self.update_stats();
 
         let fut = {
             async move {
+                // `started` and `finished` bypass the overflow policy, they must not be lost
                 if let Some(tx) = tx.upgrade() {
-                    mpsc::UnboundedSender::clone(&*tx)
-                        .start_send(ActorEvent::Exec(Box::new(move |actor, ctx| {
+                    tx.send_unchecked(
+                        Priority::Normal,
+                        ActorEvent::Exec(Box::new(move |actor, ctx| {
                             Box::pin(async move {
                                 StreamHandler::started(actor, ctx).await;
                             })
-                        })))
-                        .ok();
+                        })),
+                    )
+                    .ok();
                 } else {
                     return;
                 }
 
                 while let Some(msg) = stream.next().await {
                     if let Some(tx) = tx.upgrade() {
-                        let res = mpsc::UnboundedSender::clone(&*tx).start_send(ActorEvent::Exec(
-                            Box::new(move |actor, ctx| {
-                                Box::pin(async move {
-                                    StreamHandler::handle(actor, ctx, msg).await;
-                                })
-                            }),
-                        ));
+                        let res = tx
+                            .send(
+                                Priority::Normal,
+                                ActorEvent::Exec(Box::new(move |actor, ctx| {
+                                    Box::pin(async move {
+                                        StreamHandler::handle(actor, ctx, msg).await;
+                                    })
+                                })),
+                            )
+                            .await;
                         if res.is_err() {
                             return;
                         }
@@ -186,90 +751,288 @@ impl<A> Context<A> {
                 }
 
                 if let Some(tx) = tx.upgrade() {
-                    mpsc::UnboundedSender::clone(&*tx)
-                        .start_send(ActorEvent::Exec(Box::new(move |actor, ctx| {
+                    tx.send_unchecked(
+                        Priority::Normal,
+                        ActorEvent::Exec(Box::new(move |actor, ctx| {
                             Box::pin(async move {
                                 StreamHandler::finished(actor, ctx).await;
                             })
-                        })))
-                        .ok();
+                        })),
+                    )
+                    .ok();
                 }
 
                 if let Some(tx) = tx.upgrade() {
-                    mpsc::UnboundedSender::clone(&*tx)
-                        .start_send(ActorEvent::RemoveStream(id))
-                        .ok();
+                    tx.send_control(ActorEvent::RemoveStream(id)).ok();
                 }
             }
         };
         spawn(Abortable::new(fut, registration));
     }
 
-    /// Sends the message `msg` to self after a specified period of time.
+    /// Runs the future `fut` concurrently with the message handling of the actor, then calls `f`
+    /// with the actor and the output of the future.
     ///
-    /// We use `Sender` instead of `Addr` so that the interval doesn't keep reference to address and prevent the actor from being dropped and stopped
-
-    pub fn send_later<T>(&mut self, msg: T, after: Duration)
+    /// The mailbox keeps being processed while the future runs, `f` is called in between two
+    /// messages. The output is queued regardless of the capacity and overflow policy of the
+    /// mailbox. The future is aborted when the actor stops. Use `Context::wait` to hold back the
+    /// mailbox instead.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use xactor::*;
+    /// use std::time::Duration;
+    ///
+    /// #[message]
+    /// struct Fetch;
+    ///
+    /// #[message(result = "Vec<String>")]
+    /// struct GetLog;
+    ///
+    /// #[derive(Default)]
+    /// struct MyActor(Vec<String>);
+    ///
+    /// impl Actor for MyActor {}
+    ///
+    /// #[async_trait::async_trait]
+    /// impl Handler<Fetch> for MyActor {
+    ///     async fn handle(&mut self, ctx: &mut Context<Self>, _msg: Fetch) {
+    ///         let response = async {
+    ///             sleep(Duration::from_millis(100)).await;
+    ///             "response".to_string()
+    ///         };
+    ///         ctx.spawn(response, |actor, _ctx, response| actor.0.push(response));
+    ///     }
+    /// }
+    ///
+    /// #[async_trait::async_trait]
+    /// impl Handler<GetLog> for MyActor {
+    ///     async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: GetLog) -> Vec<String> {
+    ///         self.0.clone()
+    ///     }
+    /// }
+    ///
+    /// #[xactor::main]
+    /// async fn main() -> Result<()> {
+    ///     let addr = MyActor::start_default().await?;
+    ///     addr.send(Fetch)?;
+    ///     // Not blocked by the request in flight
+    ///     assert!(addr.call(GetLog).await?.is_empty());
+    ///
+    ///     sleep(Duration::from_millis(300)).await;
+    ///     assert_eq!(addr.call(GetLog).await?, vec!["response".to_string()]);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn spawn<F, C>(&mut self, fut: F, f: C)
     where
-        A: Handler<T>,
-        T: Message<Result = ()>,
+        A: Actor,
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+        C: FnOnce(&mut A, &mut Context<A>, F::Output) + Send + 'static,
     {
-        let sender = self.address().sender();
-        let entry = self.intervals.vacant_entry();
+        let tx = self.tx.clone();
+        let entry = self.spawned.vacant_entry();
+        let id = entry.key();
         let (handle, registration) = futures::future::AbortHandle::new_pair();
+        let handle = Arc::new(handle);
+        let token = handle.clone();
         entry.insert(handle);
 
         spawn(Abortable::new(
             async move {
-                sleep(after).await;
-                sender.send(msg).ok();
+                let output = fut.await;
+                if let Some(tx) = tx.upgrade() {
+                    // The output bypasses the overflow policy, it must not be lost
+                    tx.send_unchecked(
+                        Priority::Normal,
+                        ActorEvent::Exec(Box::new(move |actor, ctx| {
+                            Box::pin(async move {
+                                // Skip the output if the future was aborted in the meantime, the
+                                // key may have been reused by another future since
+                                let is_same = ctx
+                                    .spawned
+                                    .get(id)
+                                    .map_or(false, |entry| Arc::ptr_eq(entry, &token));
+                                if is_same {
+                                    ctx.spawned.remove(id);
+                                    f(actor, ctx, output);
+                                }
+                            })
+                        })),
+                    )
+                    .ok();
+                }
             },
             registration,
         ));
     }
 
+    /// Waits for the future `fut` before handling any other message, then calls `f` with the
+    /// actor and the output of the future.
+    ///
+    /// Unlike `Context::spawn`, the mailbox is not processed until `f` has been called. The wait
+    /// starts once the current handler returns, multiple waits complete in the order they were
+    /// added. A stop request interrupts the wait, the future is then dropped and `f` is not called.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use xactor::*;
+    /// use std::time::Duration;
+    ///
+    /// #[message]
+    /// struct Init;
+    ///
+    /// #[message(result = "bool")]
+    /// struct IsReady;
+    ///
+    /// #[derive(Default)]
+    /// struct MyActor {
+    ///     ready: bool,
+    /// }
+    ///
+    /// impl Actor for MyActor {}
+    ///
+    /// #[async_trait::async_trait]
+    /// impl Handler<Init> for MyActor {
+    ///     async fn handle(&mut self, ctx: &mut Context<Self>, _msg: Init) {
+    ///         ctx.wait(sleep(Duration::from_millis(100)), |actor, _ctx, _| actor.ready = true);
+    ///     }
+    /// }
+    ///
+    /// #[async_trait::async_trait]
+    /// impl Handler<IsReady> for MyActor {
+    ///     async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: IsReady) -> bool {
+    ///         self.ready
+    ///     }
+    /// }
+    ///
+    /// #[xactor::main]
+    /// async fn main() -> Result<()> {
+    ///     let addr = MyActor::start_default().await?;
+    ///     addr.send(Init)?;
+    ///     // Held back until the initialization has completed
+    ///     assert!(addr.call(IsReady).await?);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn wait<F, C>(&mut self, fut: F, f: C)
+    where
+        A: Actor,
+        F: Future + Send + 'static,
+        C: FnOnce(&mut A, &mut Context<A>, F::Output) + Send + 'static,
+    {
+        self.waits
+            .get_mut()
+            .unwrap()
+            .push_back(Box::new(move |actor, ctx| {
+                Box::pin(async move {
+                    let output = fut.await;
+                    f(actor, ctx, output);
+                })
+            }));
+    }
+
+    pub(crate) fn take_wait(&mut self) -> Option<ExecFn<A>> {
+        self.waits.get_mut().unwrap().pop_front()
+    }
+
+    /// Sends the message `msg` to self after a specified period of time.
+    ///
+    /// We use `Sender` instead of `Addr` so that the interval doesn't keep reference to address and prevent the actor from being dropped and stopped
+    ///
+    /// Returns a `TimerHandle` to cancel or reschedule the timer.
+    pub fn send_later<T>(&mut self, msg: T, after: Duration) -> TimerHandle
+    where
+        A: Handler<T>,
+        T: Message<Result = ()>,
+    {
+        let sender = self.address().sender();
+        let mut msg = Some(msg);
+        self.add_timer(after, false, move || {
+            if let Some(msg) = msg.take() {
+                sender.send(msg).ok();
+            }
+            false
+        })
+    }
+
     /// Sends the message  to self, at a specified fixed interval.
     /// The message is created each time using a closure `f`.
-    pub fn send_interval_with<T, F>(&mut self, f: F, dur: Duration)
+    ///
+    /// Returns a `TimerHandle` to cancel or reschedule the timer.
+    pub fn send_interval_with<T, F>(&mut self, f: F, dur: Duration) -> TimerHandle
     where
         A: Handler<T>,
         F: Fn() -> T + Sync + Send + 'static,
         T: Message<Result = ()>,
     {
         let sender = self.address().sender();
-
-        let entry = self.intervals.vacant_entry();
-        let (handle, registration) = futures::future::AbortHandle::new_pair();
-        entry.insert(handle);
-
-        spawn(Abortable::new(
-            async move {
-                loop {
-                    sleep(dur).await;
-                    if sender.send(f()).is_err() {
-                        break;
-                    }
-                }
-            },
-            registration,
-        ));
+        self.add_timer(dur, true, move || {
+            match sender.send(f()) {
+                // Skip this tick if the mailbox is full
+                Ok(()) | Err(Error::MailboxFull) => true,
+                Err(_) => false,
+            }
+        })
     }
 
     /// Sends the message `msg` to self, at a specified fixed interval.
-    pub fn send_interval<T>(&mut self, msg: T, dur: Duration)
+    ///
+    /// Returns a `TimerHandle` to cancel or reschedule the timer.
+    pub fn send_interval<T>(&mut self, msg: T, dur: Duration) -> TimerHandle
     where
         A: Handler<T>,
         T: Message<Result = ()> + Clone + Sync,
     {
-        self.send_interval_with(move || msg.clone(), dur);
+        self.send_interval_with(move || msg.clone(), dur)
+    }
+
+    fn add_timer<F>(&mut self, dur: Duration, repeat: bool, tick: F) -> TimerHandle
+    where
+        A: Actor,
+        F: FnMut() -> bool + Send + 'static,
+    {
+        let tx = self.tx.clone();
+        let entry = self.intervals.vacant_entry();
+        let (abort, registration) = futures::future::AbortHandle::new_pair();
+        let shared = Arc::new(TimerShared::new(entry.key(), abort));
+        entry.insert(shared.clone());
+        self.update_stats();
+
+        let (handle, fut) = timer(shared.clone(), dur, repeat, tick);
+        spawn(async move {
+            Abortable::new(fut, registration).await.ok();
+
+            // Free the slab entry once the timer has completed or was canceled
+            if let Some(tx) = tx.upgrade() {
+                tx.send_control(ActorEvent::RemoveTimer(shared)).ok();
+            }
+        });
+        handle
+    }
+
+    pub(crate) fn remove_timer(&mut self, timer: &Arc<TimerShared>) {
+        let is_same = self
+            .intervals
+            .get(timer.key)
+            .map_or(false, |entry| Arc::ptr_eq(entry, timer));
+        if is_same {
+            self.intervals.remove(timer.key);
+            self.update_stats();
+        }
     }
 
     /// Subscribes to a message of a specified type.
-    pub async fn subscribe<T: Message<Result = ()>>(&self) -> Result<()>
+    ///
+    /// The `Broker<T>` of the actor system the actor belongs to is used.
+    pub async fn subscribe<T: Message<Result = ()>>(&self) -> Result<(), Error>
     where
         A: Handler<T>,
     {
-        let broker = Broker::<T>::from_registry().await?;
+        let broker = self.system.service::<Broker<T>>().await?;
         let sender = self.address().sender();
         broker
             .send(Subscribe {
@@ -281,8 +1044,8 @@ impl<A> Context<A> {
     }
 
     /// Unsubscribe to a message of a specified type.
-    pub async fn unsubscribe<T: Message<Result = ()>>(&self) -> Result<()> {
-        let broker = Broker::<T>::from_registry().await?;
+    pub async fn unsubscribe<T: Message<Result = ()>>(&self) -> Result<(), Error> {
+        let broker = self.system.service::<Broker<T>>().await?;
         broker.send(Unsubscribe { id: self.actor_id })
     }
 }
@@ -0,0 +1,87 @@
+use std::fmt;
+
+/// Errors returned by the framework.
+///
+/// It converts into `error::Error`, so it can be propagated with `?` from functions returning
+/// `xactor::Result<T>`.
+///
+/// # Examples
+///
+/// ```rust
+/// use xactor::*;
+///
+/// #[message]
+/// struct Ping;
+///
+/// struct MyActor;
+///
+/// impl Actor for MyActor {}
+///
+/// #[async_trait::async_trait]
+/// impl Handler<Ping> for MyActor {
+///     async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Ping) {}
+/// }
+///
+/// #[xactor::main]
+/// async fn main() -> Result<()> {
+///     let mut addr = MyActor.start().await?;
+///     addr.stop(None)?;
+///     addr.clone().wait_for_stop().await;
+///
+///     match addr.send(Ping) {
+///         Err(Error::ActorStopped) => {}
+///         _ => unreachable!(),
+///     }
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The actor has stopped, or all references to its address have been dropped.
+    ActorStopped,
+
+    /// The mailbox of the actor is full, see `OverflowPolicy`.
+    MailboxFull,
+
+    /// The call did not complete within the specified timeout.
+    Timeout,
+
+    /// The message was discarded before it was handled, for example by the overflow policy of
+    /// the mailbox.
+    Canceled,
+
+    /// The actor returned an error from `Actor::started`.
+    StartFailed(crate::error::Error),
+
+    /// The service with the given type name cannot be created, because it has neither been
+    /// registered nor implements `Default`.
+    ServiceNotRegistered(&'static str),
+
+    /// Another running actor is registered under the same name, see
+    /// `ActorSystem::register_name`.
+    NameTaken,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ActorStopped => f.write_str("Actor stopped"),
+            Error::MailboxFull => f.write_str("Mailbox full"),
+            Error::Timeout => f.write_str("Call timed out"),
+            Error::Canceled => f.write_str("Message canceled"),
+            Error::StartFailed(err) => write!(f, "Actor failed to start: {}", err),
+            Error::ServiceNotRegistered(name) => write!(f, "Service {} is not registered", name),
+            Error::NameTaken => f.write_str("Name already taken"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::StartFailed(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
@@ -4,7 +4,7 @@
 //!
 //! * [GitHub repository](https://github.com/sunli829/xactor)
 //! * [Cargo package](https://crates.io/crates/xactor)
-//! * Minimum supported Rust version: 1.39 or later
+//! * Minimum supported Rust version: 1.43 or later
 //!
 //! ## Features
 //!
@@ -67,9 +67,13 @@ mod addr;
 mod broker;
 mod caller;
 mod context;
+mod errors;
+mod mailbox;
 mod runtime;
 mod service;
 mod supervisor;
+mod system;
+mod timer;
 
 #[cfg(all(feature = "anyhow", feature = "eyre"))]
 compile_error!(r#"
@@ -84,19 +88,20 @@ pub use anyhow as error;
 pub use eyre as error;
 
 /// Alias of error::Result
-pub type Result<T> = error::Result<T>;
-
-/// Alias of error::Error
-pub type Error = error::Error;
+pub type Result<T, E = error::Error> = std::result::Result<T, E>;
 
 pub type ActorId = u64;
 
-pub use actor::{Actor, Handler, Message, StreamHandler};
-pub use addr::{Addr, WeakAddr};
+pub use actor::{Actor, Handler, Message, StopReason, StreamHandler};
+pub use addr::{Addr, AnyAddr, WeakAddr};
 pub use broker::Broker;
 pub use caller::{Caller, Sender};
-pub use context::Context;
+pub use context::{Context, Exit, Terminated};
+pub use errors::Error;
+pub use mailbox::{MailboxConfig, OverflowPolicy, Priority};
 pub use runtime::{block_on, sleep, spawn, timeout};
-pub use service::{LocalService, Service};
-pub use supervisor::Supervisor;
+pub use service::{CreateService, LocalService, Service};
+pub use supervisor::{Strategy, SupervisionEvent, Supervisor};
+pub use system::{ActorInfo, ActorSystem, ShutdownReport};
+pub use timer::TimerHandle;
 pub use xactor_derive::{main, message};
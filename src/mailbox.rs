@@ -0,0 +1,479 @@
+use crate::addr::ActorEvent;
+use crate::{Error, Result, StopReason};
+use futures::task::{Context, Poll, Waker};
+use futures::{Future, Stream};
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+/// Priority of a message in the mailbox of an actor.
+///
+/// High priority messages are handled before all normal priority messages, stop requests are
+/// handled before any message. Use `#[message(priority = "high")]` to set it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Handled in the order the messages were sent.
+    Normal,
+
+    /// Handled before all normal priority messages.
+    High,
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Priority::Normal
+    }
+}
+
+/// What a bounded mailbox does with a new message when it is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Apply backpressure, `Addr::send_async` and `Addr::call` wait for free space while
+    /// `Addr::try_send` fails with `Error::MailboxFull`.
+    Block,
+
+    /// Discard the new message.
+    DropNewest,
+
+    /// Discard the oldest message in the mailbox to make room for the new message, normal priority
+    /// messages are discarded first.
+    DropOldest,
+
+    /// Fail with `Error::MailboxFull` without waiting for free space.
+    Reject,
+}
+
+impl Default for OverflowPolicy {
+    fn default() -> Self {
+        OverflowPolicy::Block
+    }
+}
+
+/// Mailbox configuration of an actor.
+///
+/// The mailbox is unbounded by default. A bounded mailbox holds at most `capacity` messages,
+/// what happens to further messages is decided by its `OverflowPolicy`. Messages discarded by
+/// the policy are counted by `Context::dropped_messages`.
+///
+/// # Examples
+///
+/// ```rust
+/// use xactor::*;
+///
+/// #[message]
+/// struct Work;
+///
+/// struct MyActor;
+///
+/// impl Actor for MyActor {}
+///
+/// #[async_trait::async_trait]
+/// impl Handler<Work> for MyActor {
+///     async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Work) {}
+/// }
+///
+/// #[xactor::main]
+/// async fn main() -> Result<()> {
+///     let addr = MyActor.start_with_mailbox(MailboxConfig::bounded(16)).await?;
+///     for _ in 0..100 {
+///         // Wait for capacity instead of growing the mailbox
+///         addr.send_async(Work).await?;
+///     }
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MailboxConfig {
+    capacity: Option<usize>,
+    overflow: OverflowPolicy,
+}
+
+impl MailboxConfig {
+    /// Create an unbounded mailbox configuration.
+    pub fn unbounded() -> Self {
+        Self::default()
+    }
+
+    /// Create a mailbox configuration that holds at most `capacity` messages.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn bounded(capacity: usize) -> Self {
+        assert!(capacity > 0, "mailbox capacity must be greater than zero");
+        Self {
+            capacity: Some(capacity),
+            overflow: OverflowPolicy::default(),
+        }
+    }
+
+    /// Set the policy applied when the mailbox is full, it has no effect on unbounded mailboxes.
+    pub fn with_overflow(self, overflow: OverflowPolicy) -> Self {
+        Self { overflow, ..self }
+    }
+
+    /// Returns the capacity of the mailbox, `None` if it is unbounded.
+    pub fn capacity(&self) -> Option<usize> {
+        self.capacity
+    }
+
+    /// Returns the policy applied when the mailbox is full.
+    pub fn overflow(&self) -> OverflowPolicy {
+        self.overflow
+    }
+}
+
+struct State<A> {
+    // Control events are not counted against the capacity and are handled before any message.
+    control: VecDeque<ActorEvent<A>>,
+    // Messages, with whether the overflow policy may discard them.
+    high: VecDeque<(ActorEvent<A>, bool)>,
+    normal: VecDeque<(ActorEvent<A>, bool)>,
+    dropped: u64,
+    tx_closed: bool,
+    rx_closed: bool,
+    rx_waker: Option<Waker>,
+    // Senders blocked by the `Block` policy, woken one at a time as messages are taken out.
+    tx_wakers: VecDeque<Waker>,
+}
+
+impl<A> State<A> {
+    fn messages(&self) -> usize {
+        self.high.len() + self.normal.len()
+    }
+
+    fn pop(&mut self) -> Option<(ActorEvent<A>, bool)> {
+        if let Some(event) = self.control.pop_front() {
+            return Some((event, false));
+        }
+        self.high
+            .pop_front()
+            .or_else(|| self.normal.pop_front())
+            .map(|(event, _)| (event, true))
+    }
+
+    fn push(&mut self, priority: Priority, event: ActorEvent<A>, droppable: bool) {
+        match priority {
+            Priority::Normal => self.normal.push_back((event, droppable)),
+            Priority::High => self.high.push_back((event, droppable)),
+        }
+    }
+
+    /// Remove the oldest message that may be discarded, normal priority messages first.
+    fn evict_oldest(&mut self) -> Option<ActorEvent<A>> {
+        for lane in [&mut self.normal, &mut self.high] {
+            if let Some(index) = lane.iter().position(|(_, droppable)| *droppable) {
+                return lane.remove(index).map(|(event, _)| event);
+            }
+        }
+        None
+    }
+}
+
+struct Chan<A> {
+    config: MailboxConfig,
+    state: Mutex<State<A>>,
+}
+
+/// The sending half of an actor mailbox.
+///
+/// The mailbox is closed when the sender is dropped, `Addr` keeps it in an `Arc` so that the actor
+/// stops when all references to `Addr<A>` are dropped.
+pub(crate) struct MailboxSender<A>(Arc<Chan<A>>);
+
+/// The receiving half of an actor mailbox.
+pub(crate) struct MailboxReceiver<A>(Arc<Chan<A>>);
+
+/// Read access to the counters of an actor mailbox, it does not keep the mailbox open.
+pub(crate) struct MailboxStatus<A>(Arc<Chan<A>>);
+
+pub(crate) fn channel<A>(config: MailboxConfig) -> (MailboxSender<A>, MailboxReceiver<A>) {
+    let chan = Arc::new(Chan {
+        config,
+        state: Mutex::new(State {
+            control: VecDeque::new(),
+            high: VecDeque::new(),
+            normal: VecDeque::new(),
+            dropped: 0,
+            tx_closed: false,
+            rx_closed: false,
+            rx_waker: None,
+            tx_wakers: VecDeque::new(),
+        }),
+    });
+    (MailboxSender(chan.clone()), MailboxReceiver(chan))
+}
+
+impl<A> MailboxSender<A> {
+    /// Push a message into the mailbox without waiting for free space.
+    pub(crate) fn try_send(&self, priority: Priority, event: ActorEvent<A>) -> Result<(), Error> {
+        match self.poll_push(None, priority, &mut Some(event)) {
+            Poll::Ready(res) => res,
+            Poll::Pending => unreachable!(),
+        }
+    }
+
+    /// Push a message into the mailbox, waiting for free space if the overflow policy is `Block`.
+    pub(crate) async fn send(&self, priority: Priority, event: ActorEvent<A>) -> Result<(), Error> {
+        SendFuture {
+            sender: self,
+            priority,
+            event: Some(event),
+            waker: None,
+        }
+        .await
+    }
+
+    fn poll_push(
+        &self,
+        cx: Option<&mut Context<'_>>,
+        priority: Priority,
+        event: &mut Option<ActorEvent<A>>,
+    ) -> Poll<Result<(), Error>> {
+        let mut state = self.0.state.lock().unwrap();
+        if state.rx_closed {
+            return Poll::Ready(Err(Error::ActorStopped));
+        }
+        let mut evicted = None;
+        if let Some(capacity) = self.0.config.capacity {
+            if state.messages() >= capacity {
+                match self.0.config.overflow {
+                    OverflowPolicy::Block => {
+                        return match cx {
+                            Some(cx) => {
+                                let waker = cx.waker();
+                                if !state.tx_wakers.iter().any(|w| w.will_wake(waker)) {
+                                    state.tx_wakers.push_back(waker.clone());
+                                }
+                                Poll::Pending
+                            }
+                            None => Poll::Ready(Err(Error::MailboxFull)),
+                        };
+                    }
+                    OverflowPolicy::DropNewest => {
+                        state.dropped += 1;
+                        drop(state);
+                        event.take();
+                        return Poll::Ready(Ok(()));
+                    }
+                    OverflowPolicy::DropOldest => {
+                        evicted = state.evict_oldest();
+                        if evicted.is_some() {
+                            state.dropped += 1;
+                        }
+                    }
+                    OverflowPolicy::Reject => {
+                        state.dropped += 1;
+                        return Poll::Ready(Err(Error::MailboxFull));
+                    }
+                }
+            }
+        }
+        let event = event.take().unwrap();
+        state.push(priority, event, true);
+        let waker = state.rx_waker.take();
+        drop(state);
+        drop(evicted);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Push an event into the lane of `priority` regardless of the capacity, so that it stays
+    /// ordered with the messages of that lane but is never dropped.
+    pub(crate) fn send_unchecked(
+        &self,
+        priority: Priority,
+        event: ActorEvent<A>,
+    ) -> Result<(), Error> {
+        let mut state = self.0.state.lock().unwrap();
+        if state.rx_closed {
+            return Err(Error::ActorStopped);
+        }
+        state.push(priority, event, false);
+        let waker = state.rx_waker.take();
+        drop(state);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    /// Push a control event into the mailbox, control events ignore the capacity and are handled
+    /// before any message.
+    pub(crate) fn send_control(&self, event: ActorEvent<A>) -> Result<(), Error> {
+        let mut state = self.0.state.lock().unwrap();
+        if state.rx_closed {
+            return Err(Error::ActorStopped);
+        }
+        state.control.push_back(event);
+        let waker = state.rx_waker.take();
+        drop(state);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if the actor has stopped receiving events.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.0.state.lock().unwrap().rx_closed
+    }
+}
+
+/// The future returned by `MailboxSender::send`.
+struct SendFuture<'a, A> {
+    sender: &'a MailboxSender<A>,
+    priority: Priority,
+    event: Option<ActorEvent<A>>,
+    // The waker registered in `tx_wakers` while blocked
+    waker: Option<Waker>,
+}
+
+impl<A> Future for SendFuture<'_, A> {
+    type Output = Result<(), Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let res = this
+            .sender
+            .poll_push(Some(cx), this.priority, &mut this.event);
+        this.waker = match res {
+            Poll::Pending => Some(cx.waker().clone()),
+            Poll::Ready(_) => None,
+        };
+        res
+    }
+}
+
+impl<A> Drop for SendFuture<'_, A> {
+    fn drop(&mut self) {
+        let own_waker = match self.waker.take() {
+            Some(waker) => waker,
+            None => return,
+        };
+        let mut state = self.sender.0.state.lock().unwrap();
+        // The registration may be shared with another send of the same task, which is woken to
+        // register again
+        let len = state.tx_wakers.len();
+        state.tx_wakers.retain(|waker| !waker.will_wake(&own_waker));
+        let mut wakers = Vec::new();
+        if state.tx_wakers.len() < len {
+            wakers.push(own_waker);
+        }
+        // This sender may have been woken for a free slot it no longer takes, pass it on
+        let has_space = match self.sender.0.config.capacity {
+            Some(capacity) => state.messages() < capacity,
+            None => true,
+        };
+        if has_space {
+            wakers.extend(state.tx_wakers.pop_front());
+        }
+        drop(state);
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+impl<A> Drop for MailboxSender<A> {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap();
+        state.tx_closed = true;
+        let waker = state.rx_waker.take();
+        drop(state);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<A> MailboxReceiver<A> {
+    pub(crate) fn status(&self) -> MailboxStatus<A> {
+        MailboxStatus(self.0.clone())
+    }
+}
+
+impl<A> MailboxStatus<A> {
+    /// Returns the number of messages discarded by the overflow policy.
+    pub(crate) fn dropped(&self) -> u64 {
+        self.0.state.lock().unwrap().dropped
+    }
+
+    /// Returns the number of messages waiting in the mailbox.
+    pub(crate) fn len(&self) -> usize {
+        self.0.state.lock().unwrap().messages()
+    }
+}
+
+impl<A> Clone for MailboxStatus<A> {
+    fn clone(&self) -> Self {
+        MailboxStatus(self.0.clone())
+    }
+}
+
+impl<A> MailboxReceiver<A> {
+    /// Take the first stop request out of the mailbox, the other events stay queued.
+    pub(crate) fn poll_stop(&mut self, cx: &mut Context<'_>) -> Poll<StopReason> {
+        let mut state = self.0.state.lock().unwrap();
+        let index = state
+            .control
+            .iter()
+            .position(|event| matches!(event, ActorEvent::Stop(_)));
+        match index.and_then(|index| state.control.remove(index)) {
+            Some(ActorEvent::Stop(reason)) => Poll::Ready(reason),
+            _ => {
+                state.rx_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<A> Stream for MailboxReceiver<A> {
+    type Item = ActorEvent<A>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut state = self.0.state.lock().unwrap();
+        match state.pop() {
+            Some((event, is_message)) => {
+                // One slot was freed, so one blocked sender can proceed
+                let waker = if is_message {
+                    state.tx_wakers.pop_front()
+                } else {
+                    None
+                };
+                drop(state);
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+                Poll::Ready(Some(event))
+            }
+            None if state.tx_closed => Poll::Ready(None),
+            None => {
+                state.rx_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<A> Drop for MailboxReceiver<A> {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap();
+        state.rx_closed = true;
+        // Pending events are dropped outside of the lock, they may own addresses of this actor.
+        let queues = (
+            std::mem::take(&mut state.control),
+            std::mem::take(&mut state.high),
+            std::mem::take(&mut state.normal),
+        );
+        let tx_wakers = std::mem::take(&mut state.tx_wakers);
+        drop(state);
+        drop(queues);
+        for waker in tx_wakers {
+            waker.wake();
+        }
+    }
+}
@@ -1,18 +1,24 @@
 use crate::actor::ActorManager;
-use crate::{Actor, Addr};
-use crate::error::Result;
+use crate::{error, Actor, ActorSystem, Addr, Error, Result, Supervisor};
 use fnv::FnvHasher;
-use futures::lock::Mutex;
-use once_cell::sync::OnceCell;
+use futures::future::BoxFuture;
+use futures::{Future, FutureExt};
 use std::any::{Any, TypeId};
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::hash::BuildHasherDefault;
+use std::sync::{Arc, Mutex};
 
 /// Trait define a global service.
 ///
 /// The service is a global actor.
 /// You can use `Actor::from_registry` to get the address `Addr<A>` of the service.
+/// Use `ActorSystem::service` to get the service of a scoped actor system instead.
+///
+/// The service is created by the instance or factory registered with `Service::register` or
+/// `Service::register_factory`, or else by `CreateService::create`, which returns
+/// `Self::default()` for services that implement `Default`. If the service has stopped, the next
+/// call to `from_registry` starts a new instance.
 ///
 /// # Examples
 ///
@@ -45,31 +51,150 @@ use std::hash::BuildHasherDefault;
 ///     Ok(())
 /// }
 /// ```
+///
+/// A service constructed from configuration:
+///
+/// ```rust
+/// use xactor::*;
+///
+/// #[message(result = "String")]
+/// struct GetUrl;
+///
+/// struct Database {
+///     url: String,
+/// }
+///
+/// impl Actor for Database {}
+///
+/// impl Service for Database {}
+///
+/// // Not created by default, `from_registry` fails until the service is registered
+/// impl CreateService for Database {}
+///
+/// #[async_trait::async_trait]
+/// impl Handler<GetUrl> for Database {
+///     async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: GetUrl) -> String {
+///         self.url.clone()
+///     }
+/// }
+///
+/// #[xactor::main]
+/// async fn main() -> Result<()> {
+///     assert!(matches!(
+///         Database::from_registry().await,
+///         Err(Error::ServiceNotRegistered(_))
+///     ));
+///
+///     Database::register_factory(|| async {
+///         Ok(Database {
+///             url: "postgres://localhost".to_string(),
+///         })
+///     });
+///     let addr = Database::from_registry().await?;
+///     assert_eq!(addr.call(GetUrl).await?, "postgres://localhost");
+///     Ok(())
+/// }
+/// ```
 #[async_trait::async_trait]
-pub trait Service: Actor + Default {
-    async fn from_registry() -> Result<Addr<Self>> {
-        static REGISTRY: OnceCell<
-            Mutex<HashMap<TypeId, Box<dyn Any + Send>, BuildHasherDefault<FnvHasher>>>,
-        > = OnceCell::new();
-        let registry = REGISTRY.get_or_init(Default::default);
-        let mut registry = registry.lock().await;
+pub trait Service: Actor + CreateService {
+    async fn from_registry() -> Result<Addr<Self>, Error> {
+        ActorSystem::global().service::<Self>().await
+    }
 
-        match registry.get_mut(&TypeId::of::<Self>()) {
-            Some(addr) => Ok(addr.downcast_ref::<Addr<Self>>().unwrap().clone()),
-            None => {
-                let actor_manager = ActorManager::new();
+    /// Register `service` with the global actor system, to be started on first use.
+    ///
+    /// See `ActorSystem::register_service`.
+    fn register(service: Self) {
+        ActorSystem::global().register_service(service);
+    }
 
-                registry.insert(TypeId::of::<Self>(), Box::new(actor_manager.address()));
-                drop(registry);
+    /// Register the async factory `f` with the global actor system, it is called every time the
+    /// service is started.
+    ///
+    /// See `ActorSystem::register_service_factory`.
+    fn register_factory<F, Fut>(f: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Self>> + Send + 'static,
+    {
+        ActorSystem::global().register_service_factory(f);
+    }
 
-                actor_manager.start_actor(Self::default()).await
-            }
-        }
+    /// Returns the supervisor to start the service with, the default is `None`.
+    ///
+    /// A supervised service is restarted by the supervisor as soon as it stops, and keeps its
+    /// address across restarts.
+    ///
+    /// ```rust
+    /// use xactor::*;
+    /// use std::time::Duration;
+    ///
+    /// #[derive(Default)]
+    /// struct MyService;
+    ///
+    /// impl Actor for MyService {}
+    ///
+    /// impl Service for MyService {
+    ///     fn supervisor() -> Option<Supervisor> {
+    ///         Some(Supervisor::new(Strategy::OneForOne).with_max_restarts(10, Duration::from_secs(1)))
+    ///     }
+    /// }
+    /// ```
+    fn supervisor() -> Option<Supervisor> {
+        None
+    }
+}
+
+/// Creates a service if no instance or factory has been registered for it.
+///
+/// Implemented for every type that implements `Default`. Other services implement it with the
+/// default `create`, which returns `None`, so that `from_registry` fails with
+/// `Error::ServiceNotRegistered` until the service is registered.
+pub trait CreateService: Sized {
+    fn create() -> Option<Self> {
+        None
+    }
+}
+
+impl<T: Default> CreateService for T {
+    fn create() -> Option<Self> {
+        Some(T::default())
     }
 }
 
+/// Creates a service, see `Service::register_factory`.
+pub(crate) type ServiceFactory<S> = Arc<dyn Fn() -> BoxFuture<'static, Result<S>> + Send + Sync>;
+
+/// Returns a factory that yields `service` once and calls `create` afterwards.
+pub(crate) fn once_factory<S: Actor>(service: S, create: fn() -> Option<S>) -> ServiceFactory<S> {
+    let service = Mutex::new(Some(service));
+    Arc::new(move || {
+        let service = service.lock().unwrap().take().or_else(create);
+        async move {
+            service.ok_or_else(|| {
+                error::Error::msg(format!(
+                    "service `{}` has already been started, register a factory to restart it",
+                    std::any::type_name::<S>()
+                ))
+            })
+        }
+        .boxed()
+    })
+}
+
+/// Wraps the async factory `f` into a `ServiceFactory`.
+pub(crate) fn boxed_factory<S, F, Fut>(f: F) -> ServiceFactory<S>
+where
+    S: Actor,
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<S>> + Send + 'static,
+{
+    Arc::new(move || f().boxed())
+}
+
 thread_local! {
     static LOCAL_REGISTRY: RefCell<HashMap<TypeId, Box<dyn Any + Send>, BuildHasherDefault<FnvHasher>>> = Default::default();
+    static LOCAL_FACTORIES: RefCell<HashMap<TypeId, Box<dyn Any>, BuildHasherDefault<FnvHasher>>> = Default::default();
 }
 
 /// Trait define a local service.
@@ -77,8 +202,8 @@ thread_local! {
 /// The service is a thread local actor.
 /// You can use `Actor::from_registry` to get the address `Addr<A>` of the service.
 #[async_trait::async_trait]
-pub trait LocalService: Actor + Default {
-    async fn from_registry() -> Result<Addr<Self>> {
+pub trait LocalService: Actor + CreateService {
+    async fn from_registry() -> Result<Addr<Self>, Error> {
         let res = LOCAL_REGISTRY.with(|registry| {
             registry
                 .borrow_mut()
@@ -88,7 +213,21 @@ pub trait LocalService: Actor + Default {
         match res {
             Some(addr) => Ok(addr),
             None => {
-                let addr = ActorManager::new().start_actor(Self::default()).await?;
+                let factory = LOCAL_FACTORIES
+                    .with(|factories| {
+                        factories
+                            .borrow_mut()
+                            .remove(&TypeId::of::<Self>())
+                            .map(|factory| *factory.downcast::<ServiceFactory<Self>>().unwrap())
+                    })
+                    .or_else(|| Self::create().map(|service| once_factory(service, Self::create)))
+                    .ok_or_else(|| Error::ServiceNotRegistered(std::any::type_name::<Self>()))?;
+                let service = factory().await.map_err(Error::StartFailed)?;
+                let addr = ActorManager::new()
+                    .service()
+                    .start_actor(service)
+                    .await
+                    .map_err(Error::StartFailed)?;
                 LOCAL_REGISTRY.with(|registry| {
                     registry
                         .borrow_mut()
@@ -98,4 +237,28 @@ pub trait LocalService: Actor + Default {
             }
         }
     }
+
+    /// Register `service` with the registry of the current thread, to be started on first use.
+    fn register(service: Self) {
+        LOCAL_FACTORIES.with(|factories| {
+            factories.borrow_mut().insert(
+                TypeId::of::<Self>(),
+                Box::new(once_factory(service, Self::create)),
+            );
+        });
+    }
+
+    /// Register the async factory `f` with the registry of the current thread, it is called
+    /// when the service is first used.
+    fn register_factory<F, Fut>(f: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Self>> + Send + 'static,
+    {
+        LOCAL_FACTORIES.with(|factories| {
+            factories
+                .borrow_mut()
+                .insert(TypeId::of::<Self>(), Box::new(boxed_factory(f)));
+        });
+    }
 }
@@ -1,18 +1,587 @@
-use crate::addr::ActorEvent;
-use crate::runtime::spawn;
-use crate::{Actor, Addr, Context};
+use crate::actor::run_event_loop;
+use crate::addr::{wait_exit, ActorEvent, ExecFuture, ExitReceiver};
 use crate::error::Result;
-use futures::StreamExt;
+use crate::runtime::{sleep, spawn};
+use crate::{
+    Actor, ActorId, ActorSystem, Addr, Broker, Context, MailboxConfig, Message, StopReason,
+};
+use futures::channel::{mpsc, oneshot};
+use futures::{Future, FutureExt, StreamExt};
+use std::any::Any;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeSet, VecDeque};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How a `Supervisor` restarts its children when one of them fails.
+///
+/// A child that stops without a failure is always restarted on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Only the failed child is restarted.
+    OneForOne,
+
+    /// All children are stopped and restarted.
+    OneForAll,
+
+    /// The failed child and the children added after it are stopped and restarted.
+    RestForOne,
+}
+
+impl Default for Strategy {
+    fn default() -> Self {
+        Strategy::OneForOne
+    }
+}
+
+/// Events published by supervisors through `Broker<SupervisionEvent>`.
+///
+/// Supervisors do not start the broker, the events are only published once an actor has
+/// subscribed to them.
+///
+/// # Examples
+///
+/// ```rust
+/// use xactor::*;
+/// use futures::channel::mpsc;
+/// use futures::StreamExt;
+///
+/// #[message]
+/// struct Crash;
+///
+/// struct Worker;
+///
+/// impl Actor for Worker {}
+///
+/// #[async_trait::async_trait]
+/// impl Handler<Crash> for Worker {
+///     async fn handle(&mut self, ctx: &mut Context<Self>, _msg: Crash) {
+///         ctx.stop(Some(error::Error::msg("crashed")));
+///     }
+/// }
+///
+/// struct Monitor(mpsc::UnboundedSender<SupervisionEvent>);
+///
+/// #[async_trait::async_trait]
+/// impl Actor for Monitor {
+///     async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+///         ctx.subscribe::<SupervisionEvent>().await?;
+///         Ok(())
+///     }
+/// }
+///
+/// #[async_trait::async_trait]
+/// impl Handler<SupervisionEvent> for Monitor {
+///     async fn handle(&mut self, _ctx: &mut Context<Self>, msg: SupervisionEvent) {
+///         self.0.unbounded_send(msg).ok();
+///     }
+/// }
+///
+/// #[xactor::main]
+/// async fn main() -> Result<()> {
+///     let (tx, mut events) = mpsc::unbounded();
+///     let _monitor = Monitor(tx).start().await?;
+///     let worker = Supervisor::start(|| Worker).await?;
+///
+///     worker.send(Crash)?;
+///
+///     assert!(matches!(events.next().await, Some(SupervisionEvent::ChildStarted { .. })));
+///     assert!(matches!(events.next().await, Some(SupervisionEvent::ChildFailed { .. })));
+///     assert!(matches!(events.next().await, Some(SupervisionEvent::ChildRestarted { .. })));
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum SupervisionEvent {
+    /// A child was started for the first time.
+    ChildStarted {
+        /// The id of the supervisor.
+        supervisor: ActorId,
+
+        /// The id of the child.
+        child: ActorId,
+
+        /// The type name of the child.
+        type_name: &'static str,
+    },
+
+    /// A child stopped because of a failure, or failed to restart.
+    ChildFailed {
+        /// The id of the supervisor.
+        supervisor: ActorId,
+
+        /// The id of the child.
+        child: ActorId,
+
+        /// The type name of the child.
+        type_name: &'static str,
+
+        /// The reason the child stopped.
+        reason: StopReason,
+    },
+
+    /// A child was restarted.
+    ChildRestarted {
+        /// The id of the supervisor.
+        supervisor: ActorId,
+
+        /// The id of the child.
+        child: ActorId,
+
+        /// The type name of the child.
+        type_name: &'static str,
+
+        /// The backoff delay before the restart.
+        delay: Duration,
+    },
+
+    /// The supervisor exceeded its restart limit and stopped its children.
+    GaveUp {
+        /// The id of the supervisor.
+        supervisor: ActorId,
+    },
+}
+
+impl Message for SupervisionEvent {
+    type Result = ();
+}
+
+/// Publish `event` if a broker for supervision events is running, it is not started for it.
+async fn publish(system: &ActorSystem, event: SupervisionEvent) {
+    if let Some(mut broker) = system.running_service::<Broker<SupervisionEvent>>().await {
+        broker.publish(event).ok();
+    }
+}
+
+enum Command {
+    Start {
+        generation: u64,
+        tx_started: oneshot::Sender<Result<()>>,
+        tx_stopped: oneshot::Sender<()>,
+    },
+    Terminate(StopReason),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChildState {
+    Running,
+    Stopping,
+    Stopped,
+    Terminated,
+}
+
+type LaunchFn = Box<dyn FnOnce(usize, &Context<Supervisor>) + Send>;
+
+struct Child {
+    actor_id: ActorId,
+    type_name: &'static str,
+    state: ChildState,
+    // Incremented for every start, exits reported by previous runs are ignored
+    generation: u64,
+    rx_stopped: Option<oneshot::Receiver<()>>,
+    // Nested supervisors are kept alive by their parent until they are terminated
+    supervisor: Option<Addr<Supervisor>>,
+    stop_fn: Box<dyn Fn(StopReason) + Send>,
+    commands: mpsc::UnboundedSender<Command>,
+    rx_exit: ExitReceiver,
+    launch: Option<LaunchFn>,
+}
 
 /// Actor supervisor
 ///
 /// Supervisor gives the actor the ability to restart after failure.
 /// When the actor fails, recreate a new actor instance and replace it.
-pub struct Supervisor;
+///
+/// A supervisor owns a list of children of any actor type, and restarts them according to its
+/// `Strategy`. The addresses of the children stay valid across restarts, and the mailbox of each
+/// child is kept. A child that stops with `StopReason::Shutdown` or because all its addresses
+/// were dropped is not restarted, and once none of the children is left the supervisor stops
+/// with `StopReason::AddressesDropped`. Children are started in the order they were added, and are
+/// stopped when the supervisor stops.
+///
+/// If the children fail more than `max_restarts` times within the time window, see
+/// `with_max_restarts`, the supervisor gives up: the children and the supervisor itself are
+/// stopped with `StopReason::RestartLimitExceeded`.
+///
+/// # Examples
+///
+/// ```rust
+/// use xactor::*;
+///
+/// struct Database;
+///
+/// impl Actor for Database {}
+///
+/// struct Cache;
+///
+/// impl Actor for Cache {}
+///
+/// #[xactor::main]
+/// async fn main() -> Result<()> {
+///     // The cache is restarted whenever the database fails
+///     let mut supervisor = Supervisor::new(Strategy::RestForOne);
+///     let database: Addr<Database> = supervisor.child(|| Database);
+///     let cache: Addr<Cache> = supervisor.child(|| Cache);
+///     let mut supervisor = supervisor.start().await?;
+///
+///     supervisor.stop(None)?;
+///     supervisor.wait_for_stop().await;
+///     assert!(matches!(cache.wait_for_stop().await, StopReason::Shutdown));
+///     Ok(())
+/// }
+/// ```
+pub struct Supervisor {
+    actor_id: ActorId,
+    system: ActorSystem,
+    nested: bool,
+    strategy: Strategy,
+    max_restarts: usize,
+    within: Duration,
+    backoff: Option<(Duration, Duration)>,
+    children: Vec<Child>,
+    restarting: BTreeSet<usize>,
+    restart_scheduled: bool,
+    // Only restarts caused by a failure count toward the restart limit
+    failure_pending: bool,
+    restarts: VecDeque<Instant>,
+}
 
 impl Supervisor {
+    /// Create a supervisor without children, using the specified restart strategy.
+    ///
+    /// By default it allows 3 restarts within 5 seconds, and restarts without delay.
+    pub fn new(strategy: Strategy) -> Self {
+        Self {
+            actor_id: 0,
+            system: ActorSystem::global(),
+            nested: false,
+            strategy,
+            max_restarts: 3,
+            within: Duration::from_secs(5),
+            backoff: None,
+            children: Vec::new(),
+            restarting: BTreeSet::new(),
+            restart_scheduled: false,
+            failure_pending: false,
+            restarts: VecDeque::new(),
+        }
+    }
+
+    /// Give up after `max_restarts` restarts within `within`.
+    ///
+    /// Restarting several children at once because of the strategy counts as one restart. A child
+    /// that stopped without a failure, see `StopReason::is_failure`, is restarted without counting
+    /// toward the limit.
+    pub fn with_max_restarts(self, max_restarts: usize, within: Duration) -> Self {
+        Self {
+            max_restarts,
+            within,
+            ..self
+        }
+    }
+
+    /// Wait before restarting, starting with `min` and doubling for every restart within the
+    /// time window of `with_max_restarts`, up to `max`.
+    ///
+    /// A random jitter of up to half the delay is subtracted, so that children failing together
+    /// are not restarted at the same time.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use xactor::*;
+    /// use std::time::Duration;
+    ///
+    /// struct Connection;
+    ///
+    /// #[async_trait::async_trait]
+    /// impl Actor for Connection {
+    ///     async fn started(&mut self, _ctx: &mut Context<Self>) -> Result<()> {
+    ///         Err(error::Error::msg("connection refused"))
+    ///     }
+    /// }
+    ///
+    /// #[xactor::main]
+    /// async fn main() -> Result<()> {
+    ///     let mut supervisor = Supervisor::new(Strategy::OneForOne)
+    ///         .with_max_restarts(3, Duration::from_secs(10))
+    ///         .with_backoff(Duration::from_millis(10), Duration::from_secs(1));
+    ///     let addr: Addr<Connection> = supervisor.child(|| Connection);
+    ///
+    ///     // The first start is not retried
+    ///     assert!(supervisor.start().await.is_err());
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn with_backoff(self, min: Duration, max: Duration) -> Self {
+        Self {
+            backoff: Some((min, max)),
+            ..self
+        }
+    }
+
+    /// Add a nested supervisor as a child, returning its address.
+    ///
+    /// When the nested supervisor exceeds its restart limit, it escalates the failure: it stops
+    /// with `StopReason::RestartLimitExceeded` and this supervisor handles it like any failed
+    /// child. If this supervisor restarts it, the whole subtree is restarted and the addresses of
+    /// its children stay valid, otherwise this supervisor gives up and escalates in turn.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use xactor::*;
+    /// use std::time::Duration;
+    ///
+    /// struct Connection;
+    ///
+    /// impl Actor for Connection {}
+    ///
+    /// struct Session;
+    ///
+    /// impl Actor for Session {}
+    ///
+    /// #[xactor::main]
+    /// async fn main() -> Result<()> {
+    ///     let mut connections = Supervisor::new(Strategy::OneForAll)
+    ///         .with_max_restarts(3, Duration::from_secs(1));
+    ///     let connection: Addr<Connection> = connections.child(|| Connection);
+    ///
+    ///     let mut root = Supervisor::new(Strategy::OneForOne);
+    ///     root.supervisor(connections);
+    ///     let session: Addr<Session> = root.child(|| Session);
+    ///     root.start().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn supervisor(&mut self, supervisor: Supervisor) -> Addr<Supervisor> {
+        let supervisor = Supervisor {
+            nested: true,
+            ..supervisor
+        };
+        let template = supervisor.config();
+        let supervisor = Mutex::new(Some(supervisor));
+
+        // Restarted instances get the children from the previous instance in `post_restart`
+        let addr = self.child(move || {
+            supervisor
+                .lock()
+                .unwrap()
+                .take()
+                .unwrap_or_else(|| template.config())
+        });
+        if let Some(child) = self.children.last_mut() {
+            child.supervisor = Some(addr.clone());
+        }
+        addr
+    }
+
+    fn config(&self) -> Supervisor {
+        Supervisor {
+            nested: self.nested,
+            strategy: self.strategy,
+            max_restarts: self.max_restarts,
+            within: self.within,
+            backoff: self.backoff,
+            ..Supervisor::new(self.strategy)
+        }
+    }
+
+    /// Add a child created by `f`, returning its address.
+    ///
+    /// The child is started when the supervisor starts, `f` is called again to create a new
+    /// instance for every restart.
+    pub fn child<A, F>(&mut self, f: F) -> Addr<A>
+    where
+        A: Actor,
+        F: Fn() -> A + Send + 'static,
+    {
+        self.child_with_mailbox(MailboxConfig::default(), f)
+    }
+
+    /// Add a child created by `f` with the specified mailbox configuration, returning its address.
+    pub fn child_with_mailbox<A, F>(&mut self, mailbox: MailboxConfig, f: F) -> Addr<A>
+    where
+        A: Actor,
+        F: Fn() -> A + Send + 'static,
+    {
+        self.child_async_with_mailbox(mailbox, move |_| futures::future::ready(Ok(f())))
+    }
+
+    /// Add a child created by the async factory `f`, returning its address.
+    ///
+    /// `f` is called with the number of times the child has been restarted, `0` for the first
+    /// start. If it fails, the child is handled as if it failed in `Actor::started`: the first
+    /// start of the supervisor fails, later restarts are retried according to the restart policy.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use xactor::*;
+    ///
+    /// struct Config(String);
+    ///
+    /// impl Actor for Config {}
+    ///
+    /// async fn load_config(restarts: usize) -> Result<Config> {
+    ///     if restarts > 0 {
+    ///         // Fall back to the defaults if the configuration keeps failing
+    ///         return Ok(Config(String::new()));
+    ///     }
+    ///     Ok(Config("key = value".to_string()))
+    /// }
+    ///
+    /// #[xactor::main]
+    /// async fn main() -> Result<()> {
+    ///     let mut supervisor = Supervisor::new(Strategy::OneForOne);
+    ///     let addr: Addr<Config> = supervisor.child_async(load_config);
+    ///     supervisor.start().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn child_async<A, F, Fut>(&mut self, f: F) -> Addr<A>
+    where
+        A: Actor,
+        F: Fn(usize) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<A>> + Send + 'static,
+    {
+        self.child_async_with_mailbox(MailboxConfig::default(), f)
+    }
+
+    /// Add a child created by the async factory `f` with the specified mailbox configuration,
+    /// returning its address.
+    pub fn child_async_with_mailbox<A, F, Fut>(&mut self, mailbox: MailboxConfig, f: F) -> Addr<A>
+    where
+        A: Actor,
+        F: Fn(usize) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<A>> + Send + 'static,
+    {
+        let (tx_exit, rx_exit) = oneshot::channel();
+        let rx_exit = rx_exit.shared();
+        let (mut ctx, mut rx, tx) = Context::new(Some(rx_exit.clone()), mailbox);
+        let addr = Addr {
+            actor_id: ctx.actor_id(),
+            tx,
+            rx_exit: Some(rx_exit.clone()),
+        };
+        let weak_tx = Arc::downgrade(&addr.tx);
+        let (tx_commands, mut rx_commands) = mpsc::unbounded();
+
+        let tx_system = addr.tx.clone();
+        let launch = move |index: usize, parent: &Context<Supervisor>| {
+            // Children belong to the system of the supervisor and are services if it is one
+            let supervisor = parent.address();
+            let system = parent.system.clone();
+            ctx.system = system.clone();
+            ctx.service = parent.service;
+            let actor_id = ctx.actor_id();
+            system.register(&ctx, &tx_system);
+            drop(tx_system);
+
+            spawn(async move {
+                let mut reason = StopReason::Shutdown;
+                let mut restarts = 0;
+                let mut stopped_actor: Option<A> = None;
+                while let Some(command) = rx_commands.next().await {
+                    let (generation, tx_started, tx_stopped) = match command {
+                        Command::Start {
+                            generation,
+                            tx_started,
+                            tx_stopped,
+                        } => (generation, tx_started, tx_stopped),
+                        Command::Terminate(terminate_reason) => {
+                            reason = terminate_reason;
+                            break;
+                        }
+                    };
+
+                    if let Some(mut stopped_actor) = stopped_actor.take() {
+                        stopped_actor.pre_restart(&mut ctx, &reason).await;
+                    }
+
+                    // Create the actor
+                    let restart = restarts;
+                    restarts += 1;
+                    let mut actor = match f(restart).await {
+                        Ok(actor) => actor,
+                        Err(err) => {
+                            tx_started.send(Err(err)).ok();
+                            continue;
+                        }
+                    };
+                    if restart > 0 {
+                        actor.post_restart(&mut ctx).await;
+                    }
+
+                    // Call started
+                    if let Err(err) = actor.started(&mut ctx).await {
+                        ctx.stop_children().await;
+                        ctx.abort_tasks();
+                        tx_started.send(Err(err)).ok();
+                        continue;
+                    }
+                    ctx.restart_state = None;
+                    tx_started.send(Ok(())).ok();
+
+                    reason = run_event_loop(&mut actor, &mut ctx, &mut rx).await;
+                    ctx.stop_children().await;
+                    actor.stopped(&mut ctx, &reason).await;
+                    ctx.abort_tasks();
+                    stopped_actor = Some(actor);
+                    tx_stopped.send(()).ok();
+
+                    let exit_reason = reason.clone();
+                    let res = supervisor.tx.send_control(ActorEvent::Exec(Box::new(
+                        move |supervisor, ctx| {
+                            Box::pin(supervisor.child_exited(ctx, index, generation, exit_reason))
+                        },
+                    )));
+                    if res.is_err() {
+                        break;
+                    }
+                }
+
+                // A nested supervisor is kept stopped between restarts, its children are terminated
+                // along with it
+                let stopped_actor = &mut stopped_actor as &mut (dyn Any + Send);
+                if let Some(Some(supervisor)) = stopped_actor.downcast_mut::<Option<Supervisor>>() {
+                    supervisor.terminate_children(reason.clone()).await;
+                }
+
+                drop(rx);
+                system.unregister(actor_id);
+                tx_exit.send(reason).ok();
+            });
+        };
+
+        self.children.push(Child {
+            actor_id: addr.actor_id,
+            type_name: std::any::type_name::<A>(),
+            state: ChildState::Stopped,
+            generation: 0,
+            rx_stopped: None,
+            supervisor: None,
+            stop_fn: Box::new(move |reason| {
+                if let Some(tx) = weak_tx.upgrade() {
+                    tx.send_control(ActorEvent::Stop(reason)).ok();
+                }
+            }),
+            commands: tx_commands,
+            rx_exit,
+            launch: Some(Box::new(launch)),
+        });
+        addr
+    }
+
     /// Start a supervisor
     ///
+    /// The actor is restarted every time it stops, without limit. After a failure the restart is
+    /// delayed, starting with 10 milliseconds and doubling for every failure within 5 seconds, up
+    /// to 1 second. Use `Supervisor::new` and `with_max_restarts` to give up after repeated
+    /// failures.
+    ///
     /// # Examples
     ///
     /// ```rust
@@ -75,48 +644,391 @@ impl Supervisor {
         A: Actor,
         F: Fn() -> A + Send + 'static,
     {
-        let (mut ctx, mut rx, tx) = Context::new(None);
-        let addr = Addr {
-            actor_id: ctx.actor_id(),
-            tx,
-            rx_exit: ctx.rx_exit.clone(),
+        Self::start_with_mailbox(MailboxConfig::default(), f).await
+    }
+
+    /// Start a supervisor with the specified mailbox configuration.
+    ///
+    /// The mailbox is kept across restarts, see `MailboxConfig` for the details.
+    pub async fn start_with_mailbox<A, F>(mailbox: MailboxConfig, f: F) -> Result<Addr<A>>
+    where
+        A: Actor,
+        F: Fn() -> A + Send + 'static,
+    {
+        let mut supervisor = Supervisor::unlimited();
+        let addr = supervisor.child_with_mailbox(mailbox, f);
+        Actor::start(supervisor).await?;
+        Ok(addr)
+    }
+
+    /// Start a supervisor with an async factory, see `child_async` for the details.
+    pub async fn start_async<A, F, Fut>(f: F) -> Result<Addr<A>>
+    where
+        A: Actor,
+        F: Fn(usize) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<A>> + Send + 'static,
+    {
+        let mut supervisor = Supervisor::unlimited();
+        let addr = supervisor.child_async(f);
+        Actor::start(supervisor).await?;
+        Ok(addr)
+    }
+
+    /// The supervisor of `Supervisor::start`, which never gives up but waits longer between
+    /// repeated failures.
+    fn unlimited() -> Self {
+        Supervisor::new(Strategy::OneForOne)
+            .with_max_restarts(usize::MAX, Duration::from_secs(5))
+            .with_backoff(Duration::from_millis(10), Duration::from_secs(1))
+    }
+
+    async fn start_child(&mut self, index: usize) -> Result<()> {
+        let (tx_started, rx_started) = oneshot::channel();
+        let (tx_stopped, rx_stopped) = oneshot::channel();
+        let child = &mut self.children[index];
+        child.generation += 1;
+        child.rx_stopped = Some(rx_stopped);
+        let command = Command::Start {
+            generation: child.generation,
+            tx_started,
+            tx_stopped,
+        };
+        if child.commands.unbounded_send(command).is_err() {
+            child.state = ChildState::Terminated;
+            return Ok(());
+        }
+        match rx_started.await {
+            Ok(Ok(())) => {
+                child.state = ChildState::Running;
+                Ok(())
+            }
+            Ok(Err(err)) => Err(err),
+            Err(_) => {
+                child.state = ChildState::Terminated;
+                Ok(())
+            }
+        }
+    }
+
+    fn terminate_child(&mut self, index: usize, reason: StopReason) {
+        let child = &mut self.children[index];
+        if child.state == ChildState::Terminated {
+            return;
+        }
+        child
+            .commands
+            .unbounded_send(Command::Terminate(reason))
+            .ok();
+        if child.state == ChildState::Running || child.state == ChildState::Stopping {
+            (child.stop_fn)(StopReason::Shutdown);
+        }
+        child.state = ChildState::Terminated;
+        child.supervisor = None;
+    }
+
+    /// Stop all children in the reverse order they were added without terminating them, so that
+    /// they can be started again.
+    async fn suspend_children(&mut self) {
+        for child in self.children.iter_mut().rev() {
+            if child.state == ChildState::Running || child.state == ChildState::Stopping {
+                (child.stop_fn)(StopReason::Shutdown);
+                if let Some(rx_stopped) = child.rx_stopped.take() {
+                    rx_stopped.await.ok();
+                }
+                child.state = ChildState::Stopped;
+            }
+        }
+    }
+
+    /// Stop all children in the reverse order they were added, and wait for them to finish.
+    async fn terminate_children(&mut self, reason: StopReason) {
+        let mut exits = Vec::with_capacity(self.children.len());
+        for index in (0..self.children.len()).rev() {
+            self.terminate_child(index, reason.clone());
+            exits.push(wait_exit(self.children[index].rx_exit.clone()));
+        }
+        futures::future::join_all(exits).await;
+    }
+
+    /// Record a restart, returning the delay before it or `None` if the limit is exceeded.
+    fn next_restart_delay(&mut self) -> Option<Duration> {
+        let now = Instant::now();
+        while let Some(restarted_at) = self.restarts.front() {
+            if now.duration_since(*restarted_at) <= self.within {
+                break;
+            }
+            self.restarts.pop_front();
+        }
+        if self.restarts.len() >= self.max_restarts {
+            return None;
+        }
+
+        let delay = match self.backoff {
+            Some((min, max)) => {
+                let exp = self.restarts.len().min(31) as u32;
+                let delay = min.checked_mul(1 << exp).unwrap_or(max).min(max);
+                jitter(delay)
+            }
+            None => Duration::default(),
         };
+        self.restarts.push_back(now);
+        Some(delay)
+    }
+
+    async fn child_exited(
+        &mut self,
+        ctx: &mut Context<Self>,
+        index: usize,
+        generation: u64,
+        reason: StopReason,
+    ) {
+        if self.children[index].generation != generation {
+            return;
+        }
+        let mut exits = VecDeque::new();
+        exits.push_back((index, reason));
+        self.handle_exits(ctx, exits).await;
+    }
+
+    fn restart_scheduled<'a>(
+        &'a mut self,
+        ctx: &'a mut Context<Self>,
+        delay: Duration,
+    ) -> ExecFuture<'a> {
+        // Boxed to break the cycle with `handle_exits`, which schedules it
+        Box::pin(async move {
+            self.restart_scheduled = false;
+            let mut exits = VecDeque::new();
+            self.restart_children(delay, &mut exits).await;
+            self.handle_exits(ctx, exits).await;
+        })
+    }
+
+    async fn restart_children(
+        &mut self,
+        delay: Duration,
+        exits: &mut VecDeque<(usize, StopReason)>,
+    ) {
+        while let Some(&index) = self.restarting.iter().next() {
+            match self.children[index].state {
+                ChildState::Stopped => {}
+                ChildState::Terminated => {
+                    self.restarting.remove(&index);
+                    continue;
+                }
+                // The child is still stopping, it is restarted with the children after it once
+                // it has stopped
+                _ => return,
+            }
+            self.restarting.remove(&index);
+            match self.start_child(index).await {
+                Ok(()) => {
+                    let child = &self.children[index];
+                    publish(
+                        &self.system,
+                        SupervisionEvent::ChildRestarted {
+                            supervisor: self.actor_id,
+                            child: child.actor_id,
+                            type_name: child.type_name,
+                            delay,
+                        },
+                    )
+                    .await;
+                }
+                Err(err) => exits.push_back((index, StopReason::Failed(Arc::new(err)))),
+            }
+        }
+    }
 
-        // Create the actor
-        let mut actor = f();
-
-        // Call started
-        actor.started(&mut ctx).await?;
-
-        spawn({
-            async move {
-                'restart_loop: loop {
-                    'event_loop: loop {
-                        match rx.next().await {
-                            None => break 'restart_loop,
-                            Some(ActorEvent::Stop(_err)) => break 'event_loop,
-                            Some(ActorEvent::Exec(f)) => f(&mut actor, &mut ctx).await,
-                            Some(ActorEvent::RemoveStream(id)) => {
-                                if ctx.streams.contains(id) {
-                                    ctx.streams.remove(id);
+    async fn handle_exits(
+        &mut self,
+        ctx: &mut Context<Self>,
+        mut exits: VecDeque<(usize, StopReason)>,
+    ) {
+        while let Some((index, reason)) = exits.pop_front() {
+            let child = &mut self.children[index];
+            if child.state == ChildState::Terminated {
+                continue;
+            }
+            let requested = child.state == ChildState::Stopping;
+            child.state = ChildState::Stopped;
+
+            if !requested {
+                match reason {
+                    StopReason::AddressesDropped | StopReason::Shutdown => {
+                        self.terminate_child(index, reason);
+                        // Nothing is left to supervise, this also releases a nested supervisor
+                        // kept alive by its parent
+                        if self
+                            .children
+                            .iter()
+                            .all(|child| child.state == ChildState::Terminated)
+                        {
+                            ctx.stop_with_reason(StopReason::AddressesDropped);
+                            return;
+                        }
+                    }
+                    reason => {
+                        if reason.is_failure() {
+                            self.failure_pending = true;
+                            publish(
+                                &self.system,
+                                SupervisionEvent::ChildFailed {
+                                    supervisor: self.actor_id,
+                                    child: child.actor_id,
+                                    type_name: child.type_name,
+                                    reason: reason.clone(),
+                                },
+                            )
+                            .await;
+                        }
+                        let group = match self.strategy {
+                            _ if !reason.is_failure() => index..index + 1,
+                            Strategy::OneForOne => index..index + 1,
+                            Strategy::OneForAll => 0..self.children.len(),
+                            Strategy::RestForOne => index..self.children.len(),
+                        };
+                        for index in group.rev() {
+                            let child = &mut self.children[index];
+                            match child.state {
+                                ChildState::Running => {
+                                    (child.stop_fn)(StopReason::Shutdown);
+                                    child.state = ChildState::Stopping;
                                 }
+                                ChildState::Terminated => continue,
+                                _ => {}
                             }
+                            self.restarting.insert(index);
                         }
                     }
+                }
+            }
 
-                    actor.stopped(&mut ctx).await;
-                    ctx.abort_streams();
-                    ctx.abort_intervals();
-
-                    actor = f();
-                    actor.started(&mut ctx).await.ok();
+            // Restart once all children of the group have stopped
+            if self.restarting.is_empty()
+                || self.restart_scheduled
+                || self
+                    .children
+                    .iter()
+                    .any(|child| child.state == ChildState::Stopping)
+            {
+                continue;
+            }
+            let delay = if std::mem::take(&mut self.failure_pending) {
+                self.next_restart_delay()
+            } else {
+                Some(Duration::default())
+            };
+            match delay {
+                Some(delay) => {
+                    // Restarts run as separate events, so that a stop request can get in between
+                    self.restart_scheduled = true;
+                    let tx = ctx.tx.clone();
+                    let restart = move || {
+                        if let Some(tx) = tx.upgrade() {
+                            tx.send_control(ActorEvent::Exec(Box::new(move |supervisor, ctx| {
+                                supervisor.restart_scheduled(ctx, delay)
+                            })))
+                            .ok();
+                        }
+                    };
+                    if delay == Duration::default() {
+                        restart();
+                    } else {
+                        spawn(async move {
+                            sleep(delay).await;
+                            restart();
+                        });
+                    }
+                }
+                None => {
+                    self.restarting.clear();
+                    // A nested supervisor escalates to its parent, which decides whether the
+                    // children are restarted
+                    if !self.nested {
+                        self.terminate_children(StopReason::RestartLimitExceeded)
+                            .await;
+                    }
+                    publish(
+                        &self.system,
+                        SupervisionEvent::GaveUp {
+                            supervisor: self.actor_id,
+                        },
+                    )
+                    .await;
+                    ctx.stop_with_reason(StopReason::RestartLimitExceeded);
+                    return;
                 }
-                actor.stopped(&mut ctx).await;
-                ctx.abort_streams();
-                ctx.abort_intervals();
             }
-        });
+        }
+    }
+}
 
-        Ok(addr)
+#[async_trait::async_trait]
+impl Actor for Supervisor {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        self.actor_id = ctx.actor_id();
+        self.system = ctx.system().clone();
+        for (index, child) in self.children.iter_mut().enumerate() {
+            if let Some(launch) = child.launch.take() {
+                launch(index, ctx);
+            }
+        }
+
+        for index in 0..self.children.len() {
+            if self.children[index].state == ChildState::Terminated {
+                continue;
+            }
+            if let Err(err) = self.start_child(index).await {
+                self.terminate_children(StopReason::Shutdown).await;
+                return Err(err);
+            }
+            let child = &self.children[index];
+            let event = if child.generation == 1 {
+                SupervisionEvent::ChildStarted {
+                    supervisor: self.actor_id,
+                    child: child.actor_id,
+                    type_name: child.type_name,
+                }
+            } else {
+                SupervisionEvent::ChildRestarted {
+                    supervisor: self.actor_id,
+                    child: child.actor_id,
+                    type_name: child.type_name,
+                    delay: Duration::default(),
+                }
+            };
+            publish(&self.system, event).await;
+        }
+        Ok(())
     }
+
+    async fn stopped(&mut self, _ctx: &mut Context<Self>, _reason: &StopReason) {
+        if self.nested {
+            // Keep the children for the instance replacing this one, they are terminated when
+            // this instance is dropped instead
+            self.suspend_children().await;
+        } else {
+            self.terminate_children(StopReason::Shutdown).await;
+        }
+    }
+
+    async fn pre_restart(&mut self, ctx: &mut Context<Self>, _reason: &StopReason) {
+        ctx.set_restart_state(Mutex::new(std::mem::take(&mut self.children)));
+    }
+
+    async fn post_restart(&mut self, ctx: &mut Context<Self>) {
+        if let Some(children) = ctx.take_restart_state::<Mutex<Vec<Child>>>() {
+            self.children = children.into_inner().unwrap();
+        }
+    }
+}
+
+/// Returns a random duration between half of `delay` and `delay`.
+fn jitter(delay: Duration) -> Duration {
+    let mut hasher = RandomState::new().build_hasher();
+    Instant::now().hash(&mut hasher);
+    let random = hasher.finish();
+    let half = delay / 2;
+    half + Duration::from_nanos(random % (half.as_nanos() as u64 + 1))
 }
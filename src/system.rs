@@ -0,0 +1,644 @@
+use crate::actor::ActorManager;
+use crate::addr::{wait_exit, ActorEvent, ExitReceiver};
+use crate::context::ContextStats;
+use crate::mailbox::MailboxSender;
+use crate::runtime::timeout;
+use crate::service::{boxed_factory, once_factory, ServiceFactory};
+use crate::{Actor, ActorId, Addr, Context, Error, Result, Service, StopReason, WeakAddr};
+use fnv::FnvHasher;
+use futures::{Future, FutureExt};
+use once_cell::sync::OnceCell;
+use std::any::{Any, TypeId};
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hash};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+struct Entry {
+    type_name: &'static str,
+    service: bool,
+    started: SystemTime,
+    mailbox_len: Box<dyn Fn() -> usize + Send + Sync>,
+    stats: Arc<ContextStats>,
+    addr: Box<dyn Any + Send + Sync>,
+    stop: Box<dyn Fn() + Send + Sync>,
+    rx_exit: ExitReceiver,
+}
+
+struct NamedEntry {
+    actor_id: ActorId,
+    addr: Box<dyn Any + Send + Sync>,
+    rx_exit: Option<ExitReceiver>,
+}
+
+impl NamedEntry {
+    fn is_stopped(&self) -> bool {
+        self.rx_exit
+            .as_ref()
+            .map_or(false, |rx_exit| rx_exit.clone().now_or_never().is_some())
+    }
+}
+
+type NameTables = HashMap<TypeId, Box<dyn Any + Send>, BuildHasherDefault<FnvHasher>>;
+
+/// A name registered by an actor, returns `true` if the name still refers to the actor and
+/// removes it if the flag is set.
+type NameRef = Box<dyn Fn(&mut NameTables, bool) -> bool + Send>;
+
+#[derive(Default)]
+struct Names {
+    tables: NameTables,
+    by_actor: HashMap<ActorId, Vec<NameRef>, BuildHasherDefault<FnvHasher>>,
+}
+
+impl Names {
+    fn table<K: Hash + Eq + Send + 'static>(&mut self) -> &mut HashMap<K, NamedEntry> {
+        self.tables
+            .entry(TypeId::of::<HashMap<K, NamedEntry>>())
+            .or_insert_with(|| Box::new(HashMap::<K, NamedEntry>::new()))
+            .downcast_mut()
+            .unwrap()
+    }
+
+    fn get<Q>(&self, key: &Q) -> Option<&NamedEntry>
+    where
+        Q: ?Sized + Hash + Eq + ToOwned,
+        Q::Owned: Hash + Eq + Send + 'static,
+    {
+        self.tables
+            .get(&TypeId::of::<HashMap<Q::Owned, NamedEntry>>())?
+            .downcast_ref::<HashMap<Q::Owned, NamedEntry>>()
+            .unwrap()
+            .get(key)
+            .filter(|entry| !entry.is_stopped())
+    }
+
+    /// Forget the names of `actor_id` that no longer refer to it.
+    fn prune(&mut self, actor_id: ActorId) {
+        let Names { tables, by_actor } = self;
+        if let Some(names) = by_actor.get_mut(&actor_id) {
+            names.retain(|name| name(tables, false));
+            if names.is_empty() {
+                by_actor.remove(&actor_id);
+            }
+        }
+    }
+
+    /// Returns the string name of each actor that has one.
+    fn string_names(&self) -> HashMap<ActorId, String> {
+        let mut names = HashMap::new();
+        let table = self
+            .tables
+            .get(&TypeId::of::<HashMap<String, NamedEntry>>())
+            .and_then(|table| table.downcast_ref::<HashMap<String, NamedEntry>>());
+        for (name, entry) in table.into_iter().flatten() {
+            names.entry(entry.actor_id).or_insert_with(|| name.clone());
+        }
+        names
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    actors: Mutex<HashMap<ActorId, Entry, BuildHasherDefault<FnvHasher>>>,
+    names: Mutex<Names>,
+    services:
+        futures::lock::Mutex<HashMap<TypeId, Box<dyn Any + Send>, BuildHasherDefault<FnvHasher>>>,
+    factories: Mutex<HashMap<TypeId, Box<dyn Any + Send + Sync>, BuildHasherDefault<FnvHasher>>>,
+}
+
+/// The outcome of `ActorSystem::shutdown`.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct ShutdownReport {
+    /// The number of actors that stopped in time.
+    pub stopped: usize,
+
+    /// The id and type name of the actors that failed to stop in time.
+    pub timed_out: Vec<(ActorId, &'static str)>,
+}
+
+/// A snapshot of a running actor, returned by `ActorSystem::actors`.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ActorInfo {
+    /// The id of the actor.
+    pub actor_id: ActorId,
+
+    /// The type name of the actor.
+    pub type_name: &'static str,
+
+    /// The string name the actor is registered under, see `ActorSystem::register_name`.
+    pub name: Option<String>,
+
+    /// Whether the actor is a service.
+    pub service: bool,
+
+    /// The time the actor started.
+    pub started: SystemTime,
+
+    /// The number of messages waiting in the mailbox.
+    pub mailbox_len: usize,
+
+    /// The number of streams added with `Context::add_stream`.
+    pub streams: usize,
+
+    /// The number of timers set up with `Context::send_later` and `Context::send_interval`.
+    pub intervals: usize,
+}
+
+impl ShutdownReport {
+    /// Returns `true` if all actors stopped in time.
+    pub fn is_complete(&self) -> bool {
+        self.timed_out.is_empty()
+    }
+}
+
+/// Tracks the running actors, so that they can be shut down together.
+///
+/// Every actor is tracked by its system from the moment it has started until its
+/// `Actor::stopped` hook has completed. `#[xactor::main]` shuts the global system down when the
+/// main function returns.
+///
+/// Each system has its own registry of services, `Service::from_registry` and `Broker` use the
+/// global one. Actors started with `ActorSystem::start` belong to that system, as do their
+/// children, supervised actors and the brokers used by `Context::subscribe`, which allows
+/// isolated systems to run side by side, e.g. in tests.
+///
+/// # Examples
+///
+/// ```rust
+/// use xactor::*;
+/// use std::time::Duration;
+///
+/// struct MyActor;
+///
+/// #[async_trait::async_trait]
+/// impl Actor for MyActor {
+///     async fn stopped(&mut self, _ctx: &mut Context<Self>, reason: &StopReason) {
+///         assert!(matches!(reason, StopReason::Shutdown));
+///     }
+/// }
+///
+/// #[xactor::main]
+/// async fn main() -> Result<()> {
+///     let addr = MyActor.start().await?;
+///
+///     let report = ActorSystem::global().shutdown(Duration::from_secs(1)).await;
+///     assert!(report.is_complete());
+///     assert!(matches!(addr.wait_for_stop().await, StopReason::Shutdown));
+///     Ok(())
+/// }
+/// ```
+///
+/// Scoped systems with their own services:
+///
+/// ```rust
+/// use xactor::*;
+///
+/// #[message(result = "i32")]
+/// struct Incr;
+///
+/// #[derive(Default)]
+/// struct Counter(i32);
+///
+/// impl Actor for Counter {}
+///
+/// impl Service for Counter {}
+///
+/// #[async_trait::async_trait]
+/// impl Handler<Incr> for Counter {
+///     async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Incr) -> i32 {
+///         self.0 += 1;
+///         self.0
+///     }
+/// }
+///
+/// #[xactor::main]
+/// async fn main() -> Result<()> {
+///     let system_a = ActorSystem::new();
+///     let system_b = ActorSystem::new();
+///
+///     system_a.service::<Counter>().await?.call(Incr).await?;
+///     assert_eq!(system_a.service::<Counter>().await?.call(Incr).await?, 2);
+///     assert_eq!(system_b.service::<Counter>().await?.call(Incr).await?, 1);
+///     assert_eq!(Counter::from_registry().await?.call(Incr).await?, 1);
+///
+///     system_a.shutdown(ActorSystem::DEFAULT_SHUTDOWN_TIMEOUT).await;
+///     system_b.shutdown(ActorSystem::DEFAULT_SHUTDOWN_TIMEOUT).await;
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone, Default)]
+pub struct ActorSystem {
+    inner: Arc<Inner>,
+}
+
+impl ActorSystem {
+    /// The time `#[xactor::main]` waits for the actors to stop.
+    pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Create a new actor system, independent of the global one.
+    pub fn new() -> ActorSystem {
+        Default::default()
+    }
+
+    /// Returns the global actor system.
+    pub fn global() -> ActorSystem {
+        static SYSTEM: OnceCell<ActorSystem> = OnceCell::new();
+        SYSTEM.get_or_init(Default::default).clone()
+    }
+
+    /// Start a new actor in this system, returning its address.
+    pub async fn start<A: Actor>(&self, actor: A) -> Result<Addr<A>> {
+        ActorManager::new()
+            .in_system(self.clone())
+            .start_actor(actor)
+            .await
+    }
+
+    /// Returns the address of the service `S` of this system, starting it if necessary.
+    ///
+    /// A service that has stopped is started again, see `Service::supervisor` to restart it as
+    /// soon as it fails instead.
+    pub async fn service<S: Service>(&self) -> Result<Addr<S>, Error> {
+        let mut services = self.inner.services.lock().await;
+
+        if let Some(addr) = services.get(&TypeId::of::<S>()) {
+            let addr = addr.downcast_ref::<Addr<S>>().unwrap();
+            if !addr.is_stopped() {
+                return Ok(addr.clone());
+            }
+        }
+
+        let factory = self
+            .service_factory::<S>()
+            .ok_or_else(|| Error::ServiceNotRegistered(std::any::type_name::<S>()))?;
+
+        match S::supervisor() {
+            Some(mut supervisor) => {
+                let addr = supervisor.child_async(move |_| factory());
+
+                services.insert(TypeId::of::<S>(), Box::new(addr.clone()));
+                drop(services);
+
+                ActorManager::new()
+                    .service()
+                    .in_system(self.clone())
+                    .start_actor(supervisor)
+                    .await
+                    .map_err(Error::StartFailed)?;
+                Ok(addr)
+            }
+            None => {
+                let actor_manager = ActorManager::new().service().in_system(self.clone());
+
+                services.insert(TypeId::of::<S>(), Box::new(actor_manager.address()));
+                drop(services);
+
+                let service = factory().await.map_err(Error::StartFailed)?;
+                actor_manager
+                    .start_actor(service)
+                    .await
+                    .map_err(Error::StartFailed)
+            }
+        }
+    }
+
+    /// Returns the address of the service `S` of this system if it is running, without starting
+    /// it.
+    pub(crate) async fn running_service<S: Service>(&self) -> Option<Addr<S>> {
+        let services = self.inner.services.lock().await;
+        let addr = services
+            .get(&TypeId::of::<S>())?
+            .downcast_ref::<Addr<S>>()
+            .unwrap();
+        if addr.is_stopped() {
+            return None;
+        }
+        Some(addr.clone())
+    }
+
+    /// Register `service` with this system, to be started when the service is first used.
+    ///
+    /// The instance can only be started once, if the service stops it is replaced by
+    /// `CreateService::create`. Register a factory for services that should be restarted.
+    pub fn register_service<S: Service>(&self, service: S) {
+        self.insert_factory(once_factory(service, S::create));
+    }
+
+    /// Register the async factory `f` with this system, it is called every time the service is
+    /// started.
+    ///
+    /// A running instance of the service is not affected.
+    pub fn register_service_factory<S, F, Fut>(&self, f: F)
+    where
+        S: Service,
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<S>> + Send + 'static,
+    {
+        self.insert_factory(boxed_factory(f));
+    }
+
+    fn insert_factory<S: Service>(&self, factory: ServiceFactory<S>) {
+        self.inner
+            .factories
+            .lock()
+            .unwrap()
+            .insert(TypeId::of::<S>(), Box::new(factory));
+    }
+
+    fn service_factory<S: Service>(&self) -> Option<ServiceFactory<S>> {
+        let factory = self
+            .inner
+            .factories
+            .lock()
+            .unwrap()
+            .get(&TypeId::of::<S>())
+            .map(|factory| factory.downcast_ref::<ServiceFactory<S>>().unwrap().clone());
+        factory.or_else(|| S::create().map(|service| once_factory(service, S::create)))
+    }
+
+    /// Register the actor under `key`, so that it can be found with `ActorSystem::lookup`.
+    ///
+    /// Keys are either strings or values of any other `Clone` type, e.g. `SessionId(42)`. The name is
+    /// removed when the actor stops, or with `ActorSystem::unregister_name`. Returns
+    /// `Error::NameTaken` if another running actor is registered under the same key, and
+    /// `Error::ActorStopped` if the actor is not running in this system.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use xactor::*;
+    ///
+    /// #[derive(Hash, PartialEq, Eq, Clone)]
+    /// struct SessionId(u64);
+    ///
+    /// struct Session;
+    ///
+    /// #[async_trait::async_trait]
+    /// impl Actor for Session {
+    ///     async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+    ///         ctx.register_name(&SessionId(42))?;
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// #[xactor::main]
+    /// async fn main() -> Result<()> {
+    ///     let system = ActorSystem::global();
+    ///     let mut addr = Session.start().await?;
+    ///     system.register_name("session:42", &addr)?;
+    ///
+    ///     let session = system.lookup::<Session, _>(&SessionId(42)).unwrap();
+    ///     assert_eq!(session.actor_id(), addr.actor_id());
+    ///     let session = system.lookup::<Session, _>("session:42").unwrap();
+    ///     assert_eq!(session.actor_id(), addr.actor_id());
+    ///     drop(session);
+    ///
+    ///     addr.stop(None)?;
+    ///     addr.wait_for_stop().await;
+    ///     assert!(system.lookup::<Session, _>("session:42").is_none());
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn register_name<A, Q>(&self, key: &Q, addr: &Addr<A>) -> Result<(), Error>
+    where
+        A: Actor,
+        Q: ?Sized + Hash + Eq + ToOwned,
+        Q::Owned: Hash + Eq + Send + 'static,
+    {
+        self.insert_name(key, addr.downgrade())
+    }
+
+    pub(crate) fn insert_name<A, Q>(&self, key: &Q, addr: WeakAddr<A>) -> Result<(), Error>
+    where
+        A: Actor,
+        Q: ?Sized + Hash + Eq + ToOwned,
+        Q::Owned: Hash + Eq + Send + 'static,
+    {
+        let mut names = self.inner.names.lock().unwrap();
+        let actor_id = addr.actor_id;
+        // Checked with the names locked, so that the names cannot be added after the actor has
+        // been unregistered
+        if !self.inner.actors.lock().unwrap().contains_key(&actor_id) {
+            return Err(Error::ActorStopped);
+        }
+        match names.get(key) {
+            Some(entry) if entry.actor_id != actor_id => return Err(Error::NameTaken),
+            Some(_) => return Ok(()),
+            None => {}
+        }
+
+        let entry = NamedEntry {
+            actor_id,
+            rx_exit: addr.rx_exit.clone(),
+            addr: Box::new(addr),
+        };
+        names.table::<Q::Owned>().insert(key.to_owned(), entry);
+
+        let key = key.to_owned();
+        let name: NameRef = Box::new(move |tables, remove| {
+            let table = tables
+                .get_mut(&TypeId::of::<HashMap<Q::Owned, NamedEntry>>())
+                .and_then(|table| table.downcast_mut::<HashMap<Q::Owned, NamedEntry>>());
+            match table {
+                Some(table)
+                    if table.get(key.borrow()).map(|entry| entry.actor_id) == Some(actor_id) =>
+                {
+                    if remove {
+                        table.remove(key.borrow());
+                    }
+                    true
+                }
+                _ => false,
+            }
+        });
+        names.by_actor.entry(actor_id).or_default().push(name);
+        Ok(())
+    }
+
+    /// Remove the name `key`, returns `true` if it was registered.
+    pub fn unregister_name<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Hash + Eq + ToOwned,
+        Q::Owned: Hash + Eq + Send + 'static,
+    {
+        let mut names = self.inner.names.lock().unwrap();
+        match names.table::<Q::Owned>().remove(key) {
+            Some(entry) => {
+                names.prune(entry.actor_id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the address of the running actor registered under `key`.
+    ///
+    /// Returns `None` if there is no such actor, or it is not of type `A`.
+    pub fn lookup<A, Q>(&self, key: &Q) -> Option<Addr<A>>
+    where
+        A: Actor,
+        Q: ?Sized + Hash + Eq + ToOwned,
+        Q::Owned: Hash + Eq + Send + 'static,
+    {
+        self.lookup_weak(key)?.upgrade()
+    }
+
+    /// Returns the weak address of the running actor registered under `key`.
+    ///
+    /// Returns `None` if there is no such actor, or it is not of type `A`.
+    pub fn lookup_weak<A, Q>(&self, key: &Q) -> Option<WeakAddr<A>>
+    where
+        A: Actor,
+        Q: ?Sized + Hash + Eq + ToOwned,
+        Q::Owned: Hash + Eq + Send + 'static,
+    {
+        let names = self.inner.names.lock().unwrap();
+        names.get(key)?.addr.downcast_ref::<WeakAddr<A>>().cloned()
+    }
+
+    /// Returns the address of the running actor with the id `actor_id`.
+    ///
+    /// Returns `None` if there is no such actor, or it is not of type `A`.
+    pub fn lookup_id<A: Actor>(&self, actor_id: ActorId) -> Option<Addr<A>> {
+        let actors = self.inner.actors.lock().unwrap();
+        actors
+            .get(&actor_id)?
+            .addr
+            .downcast_ref::<WeakAddr<A>>()?
+            .upgrade()
+    }
+
+    /// Returns a snapshot of all running actors, ordered by id.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use xactor::*;
+    ///
+    /// struct MyActor;
+    ///
+    /// impl Actor for MyActor {}
+    ///
+    /// #[xactor::main]
+    /// async fn main() -> Result<()> {
+    ///     let system = ActorSystem::new();
+    ///     let addr = system.start(MyActor).await?;
+    ///     system.register_name("my-actor", &addr)?;
+    ///
+    ///     let actors = system.actors();
+    ///     assert_eq!(actors.len(), 1);
+    ///     assert_eq!(actors[0].actor_id, addr.actor_id());
+    ///     assert_eq!(actors[0].name.as_deref(), Some("my-actor"));
+    ///     assert_eq!(actors[0].mailbox_len, 0);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn actors(&self) -> Vec<ActorInfo> {
+        let mut names = self.inner.names.lock().unwrap().string_names();
+        let actors = self.inner.actors.lock().unwrap();
+        let mut actors = actors
+            .iter()
+            .map(|(actor_id, entry)| ActorInfo {
+                actor_id: *actor_id,
+                type_name: entry.type_name,
+                name: names.remove(actor_id),
+                service: entry.service,
+                started: entry.started,
+                mailbox_len: (entry.mailbox_len)(),
+                streams: entry.stats.streams.load(Ordering::Relaxed),
+                intervals: entry.stats.intervals.load(Ordering::Relaxed),
+            })
+            .collect::<Vec<_>>();
+        actors.sort_by_key(|info| info.actor_id);
+        actors
+    }
+
+    pub(crate) fn register<A: Actor>(&self, ctx: &Context<A>, tx: &Arc<MailboxSender<A>>) {
+        let rx_exit = match &ctx.rx_exit {
+            Some(rx_exit) => rx_exit.clone(),
+            None => return,
+        };
+        let actor_id = ctx.actor_id();
+        let tx = Arc::downgrade(tx);
+        let mailbox = ctx.mailbox.clone();
+        let entry = Entry {
+            type_name: std::any::type_name::<A>(),
+            service: ctx.service,
+            started: SystemTime::now(),
+            mailbox_len: Box::new(move || mailbox.len()),
+            stats: ctx.stats.clone(),
+            addr: Box::new(WeakAddr {
+                actor_id,
+                tx: tx.clone(),
+                rx_exit: Some(rx_exit.clone()),
+            }),
+            stop: Box::new(move || {
+                if let Some(tx) = tx.upgrade() {
+                    tx.send_control(ActorEvent::Stop(StopReason::Shutdown)).ok();
+                }
+            }),
+            rx_exit,
+        };
+        self.inner.actors.lock().unwrap().insert(actor_id, entry);
+    }
+
+    pub(crate) fn unregister(&self, actor_id: ActorId) {
+        let entry = self.inner.actors.lock().unwrap().remove(&actor_id);
+        drop(entry);
+
+        let mut names = self.inner.names.lock().unwrap();
+        let Names { tables, by_actor } = &mut *names;
+        for name in by_actor.remove(&actor_id).into_iter().flatten() {
+            name(tables, true);
+        }
+    }
+
+    /// Stop all actors with `StopReason::Shutdown` and wait for them, at most for `timeout`.
+    ///
+    /// Services are stopped after all other actors have stopped, so that they are still available
+    /// in `Actor::stopped`. Returns which actors failed to stop in time.
+    pub async fn shutdown(&self, timeout: Duration) -> ShutdownReport {
+        let deadline = Instant::now() + timeout;
+        let mut report = ShutdownReport::default();
+
+        for services in [false, true].iter() {
+            let actors = {
+                let actors = self.inner.actors.lock().unwrap();
+                actors
+                    .iter()
+                    .filter(|(_, entry)| entry.service == *services)
+                    .map(|(actor_id, entry)| {
+                        (entry.stop)();
+                        (*actor_id, entry.type_name, entry.rx_exit.clone())
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+            let exits = actors.into_iter().map(|(actor_id, type_name, rx_exit)| {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                async move {
+                    let stopped = timeout_exit(remaining, rx_exit).await;
+                    (actor_id, type_name, stopped)
+                }
+            });
+            for (actor_id, type_name, stopped) in futures::future::join_all(exits).await {
+                if stopped {
+                    report.stopped += 1;
+                } else {
+                    report.timed_out.push((actor_id, type_name));
+                }
+            }
+        }
+
+        report
+    }
+}
+
+async fn timeout_exit(dur: Duration, rx_exit: ExitReceiver) -> bool {
+    timeout(dur, wait_exit(rx_exit)).await.is_ok()
+}
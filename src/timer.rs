@@ -0,0 +1,151 @@
+use crate::runtime::sleep;
+use futures::channel::mpsc;
+use futures::future::{AbortHandle, Either};
+use futures::{FutureExt, StreamExt};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The state of a timer shared between its handles, its task and the slab of the context.
+pub(crate) struct TimerShared {
+    pub(crate) key: usize,
+    abort: AbortHandle,
+    fired: AtomicBool,
+    canceled: AtomicBool,
+}
+
+impl TimerShared {
+    pub(crate) fn new(key: usize, abort: AbortHandle) -> Self {
+        Self {
+            key,
+            abort,
+            fired: AtomicBool::new(false),
+            canceled: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn cancel(&self) {
+        // The timer task only notices the abort when it is polled again
+        self.canceled.store(true, Ordering::Relaxed);
+        self.abort.abort();
+    }
+}
+
+/// A handle to a timer set up with `Context::send_later` or `Context::send_interval`.
+///
+/// Dropping the handle does not cancel the timer.
+///
+/// # Examples
+///
+/// ```rust
+/// use xactor::*;
+/// use std::time::Duration;
+///
+/// #[message]
+/// struct Timeout;
+///
+/// #[message]
+/// struct Activity;
+///
+/// struct Session {
+///     timeout: Option<TimerHandle>,
+/// }
+///
+/// #[async_trait::async_trait]
+/// impl Actor for Session {
+///     async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+///         self.timeout = Some(ctx.send_later(Timeout, Duration::from_millis(100)));
+///         Ok(())
+///     }
+/// }
+///
+/// #[async_trait::async_trait]
+/// impl Handler<Activity> for Session {
+///     async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Activity) {
+///         // Push the timeout back
+///         if let Some(timeout) = &self.timeout {
+///             timeout.reschedule(Duration::from_millis(100));
+///         }
+///     }
+/// }
+///
+/// #[async_trait::async_trait]
+/// impl Handler<Timeout> for Session {
+///     async fn handle(&mut self, ctx: &mut Context<Self>, _msg: Timeout) {
+///         ctx.stop(None);
+///     }
+/// }
+///
+/// #[xactor::main]
+/// async fn main() -> Result<()> {
+///     let addr = Session { timeout: None }.start().await?;
+///     for _ in 0..3 {
+///         sleep(Duration::from_millis(50)).await;
+///         addr.send(Activity)?;
+///     }
+///     addr.wait_for_stop().await;
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone)]
+pub struct TimerHandle {
+    shared: Arc<TimerShared>,
+    tx_reschedule: mpsc::UnboundedSender<Duration>,
+}
+
+impl TimerHandle {
+    /// Cancel the timer, the message is not sent again.
+    pub fn cancel(&self) {
+        self.shared.cancel();
+    }
+
+    /// Restart the timer with the delay `dur`, for an interval it also becomes the new period.
+    ///
+    /// Returns `false` if the timer has already completed or was canceled.
+    pub fn reschedule(&self, dur: Duration) -> bool {
+        !self.shared.canceled.load(Ordering::Relaxed)
+            && self.tx_reschedule.unbounded_send(dur).is_ok()
+    }
+
+    /// Returns `true` if the timer has fired at least once.
+    pub fn has_fired(&self) -> bool {
+        self.shared.fired.load(Ordering::Relaxed)
+    }
+}
+
+/// Returns the handle and the future of a timer that calls `tick` after `dur`, and then every
+/// `dur` if `repeat` is set, until `tick` returns `false`.
+pub(crate) fn timer<F>(
+    shared: Arc<TimerShared>,
+    mut dur: Duration,
+    repeat: bool,
+    mut tick: F,
+) -> (TimerHandle, impl std::future::Future<Output = ()>)
+where
+    F: FnMut() -> bool,
+{
+    let (tx_reschedule, mut rx_reschedule) = mpsc::unbounded();
+    let handle = TimerHandle {
+        shared: shared.clone(),
+        tx_reschedule: tx_reschedule.clone(),
+    };
+
+    let fut = async move {
+        // Keep a sender, so that the channel only closes when the timer completes
+        let _tx_reschedule = tx_reschedule;
+        let mut delay = sleep(dur).boxed();
+        loop {
+            match futures::future::select(delay, rx_reschedule.next()).await {
+                Either::Left(_) => {
+                    shared.fired.store(true, Ordering::Relaxed);
+                    if !tick() || !repeat {
+                        return;
+                    }
+                }
+                Either::Right((new_dur, _)) => dur = new_dur.unwrap_or(dur),
+            }
+            delay = sleep(dur).boxed();
+        }
+    };
+    (handle, fut)
+}
@@ -0,0 +1,364 @@
+use futures::channel::{mpsc, oneshot};
+use futures::future::join_all;
+use futures::StreamExt;
+use std::time::Duration;
+use xactor::*;
+
+#[message]
+struct Work;
+
+#[message(result = "usize")]
+struct Count;
+
+#[derive(Default)]
+struct Worker(usize);
+
+impl Actor for Worker {}
+
+#[async_trait::async_trait]
+impl Handler<Work> for Worker {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Work) {
+        sleep(Duration::from_millis(1)).await;
+        self.0 += 1;
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Count> for Worker {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Count) -> usize {
+        self.0
+    }
+}
+
+#[test]
+fn blocked_senders_all_proceed() {
+    block_on(async {
+        let addr = Worker::default()
+            .start_with_mailbox(MailboxConfig::bounded(1))
+            .await
+            .unwrap();
+        let sends = (0..20).map(|_| addr.send_async(Work));
+        for res in join_all(sends).await {
+            res.unwrap();
+        }
+        assert_eq!(addr.call(Count).await.unwrap(), 20);
+    });
+}
+
+#[test]
+fn canceled_blocked_sender_does_not_stall_others() {
+    block_on(async {
+        let addr = Worker::default()
+            .start_with_mailbox(MailboxConfig::bounded(1))
+            .await
+            .unwrap();
+        // Fill the mailbox, then give up on a blocked send
+        addr.send(Work).unwrap();
+        let _ = timeout(Duration::from_micros(10), addr.send_async(Work)).await;
+
+        let sends = (0..5).map(|_| addr.send_async(Work));
+        let res = timeout(Duration::from_secs(5), join_all(sends)).await;
+        assert!(res.unwrap().into_iter().all(|res| res.is_ok()));
+    });
+}
+
+#[message]
+struct Hold(oneshot::Sender<()>, oneshot::Receiver<()>);
+
+#[message]
+struct Record(usize);
+
+#[message(result = "u64")]
+struct Dropped;
+
+struct Recorder(mpsc::UnboundedSender<usize>);
+
+impl Actor for Recorder {}
+
+#[async_trait::async_trait]
+impl Handler<Hold> for Recorder {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: Hold) {
+        msg.0.send(()).ok();
+        msg.1.await.ok();
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Record> for Recorder {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: Record) {
+        self.0.unbounded_send(msg.0).ok();
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Dropped> for Recorder {
+    async fn handle(&mut self, ctx: &mut Context<Self>, _msg: Dropped) -> u64 {
+        ctx.dropped_messages()
+    }
+}
+
+/// Starts a recorder with a mailbox of capacity 2 and blocks it in a handler, returns the
+/// address, the receiver of the recorded messages and the sender to unblock it.
+async fn blocked_recorder(
+    overflow: OverflowPolicy,
+) -> (
+    Addr<Recorder>,
+    mpsc::UnboundedReceiver<usize>,
+    oneshot::Sender<()>,
+) {
+    let (tx_recorded, rx_recorded) = mpsc::unbounded();
+    let mailbox = MailboxConfig::bounded(2).with_overflow(overflow);
+    let addr = Recorder(tx_recorded)
+        .start_with_mailbox(mailbox)
+        .await
+        .unwrap();
+    let (tx_held, rx_held) = oneshot::channel();
+    let (tx_release, rx_release) = oneshot::channel();
+    addr.send(Hold(tx_held, rx_release)).unwrap();
+    rx_held.await.unwrap();
+    (addr, rx_recorded, tx_release)
+}
+
+async fn recorded(rx: &mut mpsc::UnboundedReceiver<usize>, n: usize) -> Vec<usize> {
+    let mut res = Vec::new();
+    for _ in 0..n {
+        res.push(rx.next().await.unwrap());
+    }
+    res
+}
+
+#[test]
+fn overflow_block() {
+    block_on(async {
+        let (addr, mut rx, release) = blocked_recorder(OverflowPolicy::Block).await;
+        addr.send(Record(1)).unwrap();
+        addr.send(Record(2)).unwrap();
+        assert!(matches!(addr.send(Record(3)), Err(Error::MailboxFull)));
+
+        let send = addr.send_async(Record(3));
+        futures::pin_mut!(send);
+        assert!(futures::poll!(send.as_mut()).is_pending());
+        release.send(()).unwrap();
+        send.await.unwrap();
+
+        assert_eq!(recorded(&mut rx, 3).await, vec![1, 2, 3]);
+        assert_eq!(addr.call(Dropped).await.unwrap(), 0);
+    });
+}
+
+#[test]
+fn overflow_drop_newest() {
+    block_on(async {
+        let (addr, mut rx, release) = blocked_recorder(OverflowPolicy::DropNewest).await;
+        for i in 1..=4 {
+            addr.send(Record(i)).unwrap();
+        }
+        release.send(()).unwrap();
+
+        assert_eq!(recorded(&mut rx, 2).await, vec![1, 2]);
+        assert_eq!(addr.call(Dropped).await.unwrap(), 2);
+    });
+}
+
+#[test]
+fn overflow_drop_oldest() {
+    block_on(async {
+        let (addr, mut rx, release) = blocked_recorder(OverflowPolicy::DropOldest).await;
+        for i in 1..=4 {
+            addr.send(Record(i)).unwrap();
+        }
+        release.send(()).unwrap();
+
+        assert_eq!(recorded(&mut rx, 2).await, vec![3, 4]);
+        assert_eq!(addr.call(Dropped).await.unwrap(), 2);
+    });
+}
+
+#[test]
+fn overflow_reject() {
+    block_on(async {
+        let (addr, mut rx, release) = blocked_recorder(OverflowPolicy::Reject).await;
+        addr.send(Record(1)).unwrap();
+        addr.send(Record(2)).unwrap();
+        assert!(matches!(addr.send(Record(3)), Err(Error::MailboxFull)));
+        release.send(()).unwrap();
+
+        assert_eq!(recorded(&mut rx, 2).await, vec![1, 2]);
+        assert_eq!(addr.call(Dropped).await.unwrap(), 1);
+    });
+}
+
+struct Streamer;
+
+impl Actor for Streamer {}
+
+#[async_trait::async_trait]
+impl StreamHandler<usize> for Streamer {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: usize) {}
+}
+
+#[message]
+struct AddStream;
+
+#[async_trait::async_trait]
+impl Handler<AddStream> for Streamer {
+    async fn handle(&mut self, ctx: &mut Context<Self>, _msg: AddStream) {
+        ctx.add_stream(futures::stream::iter(0..100));
+        // Let the stream overflow the mailbox before any item is handled
+        sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[test]
+fn stream_finished_is_never_dropped() {
+    block_on(async {
+        for overflow in [OverflowPolicy::DropNewest, OverflowPolicy::DropOldest] {
+            let mailbox = MailboxConfig::bounded(1).with_overflow(overflow);
+            let addr = Streamer.start_with_mailbox(mailbox).await.unwrap();
+            addr.send(AddStream).unwrap();
+            // The default `StreamHandler::finished` stops the actor
+            let reason = timeout(Duration::from_secs(5), addr.wait_for_stop()).await;
+            assert!(matches!(reason, Ok(StopReason::Normal)));
+        }
+    });
+}
+
+#[message(priority = "high")]
+struct Urgent(usize);
+
+#[async_trait::async_trait]
+impl Handler<Urgent> for Recorder {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: Urgent) {
+        self.0.unbounded_send(msg.0).ok();
+    }
+}
+
+#[test]
+fn high_priority_overtakes_normal() {
+    block_on(async {
+        let (addr, mut rx, release) = blocked_recorder(OverflowPolicy::Block).await;
+        addr.send(Record(1)).unwrap();
+        addr.send(Urgent(2)).unwrap();
+        release.send(()).unwrap();
+
+        assert_eq!(recorded(&mut rx, 2).await, vec![2, 1]);
+    });
+}
+
+#[test]
+fn stop_overtakes_backlog() {
+    block_on(async {
+        let (mut addr, mut rx, release) = blocked_recorder(OverflowPolicy::Block).await;
+        addr.send(Record(1)).unwrap();
+        addr.send(Record(2)).unwrap();
+        addr.stop(None).unwrap();
+        release.send(()).unwrap();
+
+        assert!(matches!(addr.wait_for_stop().await, StopReason::Normal));
+        assert_eq!(rx.next().await, None);
+    });
+}
+
+#[message]
+struct SpawnRecv(oneshot::Receiver<usize>);
+
+#[async_trait::async_trait]
+impl Handler<SpawnRecv> for Recorder {
+    async fn handle(&mut self, ctx: &mut Context<Self>, msg: SpawnRecv) {
+        ctx.spawn(msg.0, |actor, _ctx, value| {
+            actor.0.unbounded_send(value.unwrap()).ok();
+        });
+    }
+}
+
+#[test]
+fn spawn_output_is_never_dropped() {
+    block_on(async {
+        let (tx_recorded, mut rx) = mpsc::unbounded();
+        let mailbox = MailboxConfig::bounded(1).with_overflow(OverflowPolicy::DropNewest);
+        let addr = Recorder(tx_recorded)
+            .start_with_mailbox(mailbox)
+            .await
+            .unwrap();
+        let (tx_value, rx_value) = oneshot::channel();
+        addr.call(SpawnRecv(rx_value)).await.unwrap();
+
+        let (tx_held, rx_held) = oneshot::channel();
+        let (release, rx_release) = oneshot::channel();
+        addr.send(Hold(tx_held, rx_release)).unwrap();
+        rx_held.await.unwrap();
+        addr.send(Record(1)).unwrap();
+
+        // The output arrives while the mailbox is full
+        tx_value.send(2).unwrap();
+        sleep(Duration::from_millis(50)).await;
+        release.send(()).unwrap();
+
+        assert_eq!(recorded(&mut rx, 2).await, vec![1, 2]);
+    });
+}
+
+#[message]
+struct WaitForever;
+
+#[async_trait::async_trait]
+impl Handler<WaitForever> for Recorder {
+    async fn handle(&mut self, ctx: &mut Context<Self>, _msg: WaitForever) {
+        ctx.wait(futures::future::pending::<()>(), |_, _, _| {});
+    }
+}
+
+#[test]
+fn stop_interrupts_wait() {
+    block_on(async {
+        let (tx_recorded, mut rx) = mpsc::unbounded();
+        let mut addr = Recorder(tx_recorded).start().await.unwrap();
+        addr.call(WaitForever).await.unwrap();
+        // Held back by the wait
+        addr.send(Record(1)).unwrap();
+        addr.stop(None).unwrap();
+
+        let reason = timeout(Duration::from_secs(5), addr.wait_for_stop()).await;
+        assert!(matches!(reason, Ok(StopReason::Normal)));
+        assert_eq!(rx.next().await, None);
+    });
+}
+
+#[message]
+struct Respawn(oneshot::Receiver<usize>);
+
+#[async_trait::async_trait]
+impl Handler<Respawn> for Recorder {
+    async fn handle(&mut self, ctx: &mut Context<Self>, msg: Respawn) {
+        ctx.abort_spawned();
+        ctx.spawn(msg.0, |actor, _ctx, value| {
+            actor.0.unbounded_send(value.unwrap()).ok();
+        });
+    }
+}
+
+#[test]
+fn aborted_spawn_output_is_skipped() {
+    block_on(async {
+        let (tx_recorded, mut rx) = mpsc::unbounded();
+        let addr = Recorder(tx_recorded).start().await.unwrap();
+        let (tx_a, rx_a) = oneshot::channel();
+        addr.call(SpawnRecv(rx_a)).await.unwrap();
+
+        let (tx_held, rx_held) = oneshot::channel();
+        let (release, rx_release) = oneshot::channel();
+        addr.send(Hold(tx_held, rx_release)).unwrap();
+        rx_held.await.unwrap();
+
+        // The output of the first future is queued behind the abort, and the second future
+        // reuses its slot
+        let (tx_b, rx_b) = oneshot::channel();
+        addr.send(Respawn(rx_b)).unwrap();
+        tx_a.send(1).unwrap();
+        sleep(Duration::from_millis(50)).await;
+        release.send(()).unwrap();
+
+        tx_b.send(2).unwrap();
+        assert_eq!(recorded(&mut rx, 1).await, vec![2]);
+    });
+}
@@ -0,0 +1,60 @@
+use xactor::*;
+
+struct Named;
+
+#[async_trait::async_trait]
+impl Actor for Named {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        ctx.register_name("named")?;
+        Ok(())
+    }
+}
+
+struct Plain;
+
+impl Actor for Plain {}
+
+#[test]
+fn names_registered_in_started_resolve() {
+    block_on(async {
+        let system = ActorSystem::new();
+        let addr = system.start(Named).await.unwrap();
+        let named = system.lookup::<Named, _>("named").unwrap();
+        assert_eq!(named.actor_id(), addr.actor_id());
+    });
+}
+
+#[test]
+fn actors_of_other_systems_cannot_be_named() {
+    block_on(async {
+        let system = ActorSystem::new();
+        let other = ActorSystem::new();
+        let addr = other.start(Plain).await.unwrap();
+        assert!(matches!(
+            system.register_name("plain", &addr),
+            Err(Error::ActorStopped)
+        ));
+        assert!(system.lookup::<Plain, _>("plain").is_none());
+        assert!(other.register_name("plain", &addr).is_ok());
+    });
+}
+
+#[test]
+fn unregistered_name_can_be_taken_by_another_actor() {
+    block_on(async {
+        let system = ActorSystem::new();
+        let mut first = system.start(Plain).await.unwrap();
+        let second = system.start(Plain).await.unwrap();
+
+        system.register_name("plain", &first).unwrap();
+        assert!(system.unregister_name("plain"));
+        assert!(!system.unregister_name("plain"));
+        system.register_name("plain", &second).unwrap();
+
+        // Stopping the previous owner leaves the new registration alone
+        first.stop(None).unwrap();
+        first.wait_for_stop().await;
+        let plain = system.lookup::<Plain, _>("plain").unwrap();
+        assert_eq!(plain.actor_id(), second.actor_id());
+    });
+}
@@ -0,0 +1,88 @@
+use xactor::*;
+
+#[message(result = "i32")]
+struct Incr;
+
+#[message]
+struct Quit;
+
+#[message]
+struct Fail;
+
+#[derive(Default)]
+struct Counter(i32);
+
+impl Actor for Counter {}
+
+impl Service for Counter {}
+
+#[async_trait::async_trait]
+impl Handler<Incr> for Counter {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Incr) -> i32 {
+        self.0 += 1;
+        self.0
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Quit> for Counter {
+    async fn handle(&mut self, ctx: &mut Context<Self>, _msg: Quit) {
+        ctx.stop(None);
+    }
+}
+
+#[test]
+fn stopped_service_is_started_again() {
+    block_on(async {
+        let addr = Counter::from_registry().await.unwrap();
+        assert_eq!(addr.call(Incr).await.unwrap(), 1);
+        addr.send(Quit).unwrap();
+        addr.clone().wait_for_stop().await;
+
+        let new_addr = Counter::from_registry().await.unwrap();
+        assert_ne!(new_addr.actor_id(), addr.actor_id());
+        assert_eq!(new_addr.call(Incr).await.unwrap(), 1);
+    });
+}
+
+#[derive(Default)]
+struct Supervised(i32);
+
+impl Actor for Supervised {}
+
+impl Service for Supervised {
+    fn supervisor() -> Option<Supervisor> {
+        Some(Supervisor::new(Strategy::OneForOne))
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Incr> for Supervised {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Incr) -> i32 {
+        self.0 += 1;
+        self.0
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Fail> for Supervised {
+    async fn handle(&mut self, ctx: &mut Context<Self>, _msg: Fail) {
+        ctx.stop(Some(error::Error::msg("failed")));
+    }
+}
+
+#[test]
+fn supervised_service_is_restarted_at_the_same_address() {
+    block_on(async {
+        let addr = Supervised::from_registry().await.unwrap();
+        assert_eq!(addr.call(Incr).await.unwrap(), 1);
+        assert_eq!(addr.call(Incr).await.unwrap(), 2);
+        addr.send(Fail).unwrap();
+
+        // Handled by the restarted instance
+        assert_eq!(addr.call(Incr).await.unwrap(), 1);
+        let same_addr = Supervised::from_registry().await.unwrap();
+        assert_eq!(same_addr.actor_id(), addr.actor_id());
+        assert_eq!(same_addr.call(Incr).await.unwrap(), 2);
+    });
+}
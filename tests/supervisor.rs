@@ -0,0 +1,354 @@
+use futures::channel::mpsc;
+use futures::StreamExt;
+use std::time::Duration;
+use xactor::*;
+
+#[message(result = "u32")]
+struct Panic;
+
+#[message(result = "u32")]
+struct Ping;
+
+struct Fragile(mpsc::UnboundedSender<&'static str>);
+
+#[async_trait::async_trait]
+impl Actor for Fragile {
+    async fn started(&mut self, _ctx: &mut Context<Self>) -> Result<()> {
+        self.0.unbounded_send("started").ok();
+        Ok(())
+    }
+
+    async fn stopped(&mut self, _ctx: &mut Context<Self>, reason: &StopReason) {
+        assert!(matches!(reason, StopReason::Panicked(msg) if msg == "boom"));
+        self.0.unbounded_send("stopped").ok();
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Panic> for Fragile {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Panic) -> u32 {
+        panic!("boom");
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Ping> for Fragile {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Ping) -> u32 {
+        1
+    }
+}
+
+#[test]
+fn panicked_child_is_stopped_and_restarted() {
+    block_on(async {
+        let (tx, mut events) = mpsc::unbounded();
+        let addr = Supervisor::start(move || Fragile(tx.clone()))
+            .await
+            .unwrap();
+        assert_eq!(events.next().await, Some("started"));
+
+        assert!(matches!(addr.call(Panic).await, Err(Error::ActorStopped)));
+        assert_eq!(events.next().await, Some("stopped"));
+        assert_eq!(events.next().await, Some("started"));
+        assert_eq!(addr.call(Ping).await.unwrap(), 1);
+    });
+}
+
+#[test]
+fn start_restarts_without_limit() {
+    block_on(async {
+        let (tx, mut events) = mpsc::unbounded();
+        let addr = Supervisor::start(move || Fragile(tx.clone()))
+            .await
+            .unwrap();
+        for _ in 0..5 {
+            assert!(addr.call(Panic).await.is_err());
+        }
+        assert_eq!(addr.call(Ping).await.unwrap(), 1);
+        assert_eq!(events.by_ref().take(11).count().await, 11);
+    });
+}
+
+#[message]
+struct Fail;
+
+#[message]
+struct Quit;
+
+struct Member {
+    name: &'static str,
+    slow_stop: bool,
+    events: mpsc::UnboundedSender<&'static str>,
+}
+
+#[async_trait::async_trait]
+impl Actor for Member {
+    async fn started(&mut self, _ctx: &mut Context<Self>) -> Result<()> {
+        self.events.unbounded_send(self.name).ok();
+        Ok(())
+    }
+
+    async fn stopped(&mut self, _ctx: &mut Context<Self>, _reason: &StopReason) {
+        if self.slow_stop {
+            sleep(Duration::from_millis(300)).await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Fail> for Member {
+    async fn handle(&mut self, ctx: &mut Context<Self>, _msg: Fail) {
+        ctx.stop(Some(error::Error::msg("failed")));
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Quit> for Member {
+    async fn handle(&mut self, ctx: &mut Context<Self>, _msg: Quit) {
+        ctx.stop(None);
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Ping> for Member {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Ping) -> u32 {
+        1
+    }
+}
+
+fn member(
+    supervisor: &mut Supervisor,
+    name: &'static str,
+    slow_stop: bool,
+    events: &mpsc::UnboundedSender<&'static str>,
+) -> Addr<Member> {
+    let events = events.clone();
+    supervisor.child(move || Member {
+        name,
+        slow_stop,
+        events: events.clone(),
+    })
+}
+
+#[test]
+fn clean_stops_do_not_count_toward_restart_limit() {
+    block_on(async {
+        let (tx, mut events) = mpsc::unbounded();
+        let mut supervisor =
+            Supervisor::new(Strategy::OneForOne).with_max_restarts(1, Duration::from_secs(10));
+        let a = member(&mut supervisor, "a", false, &tx);
+        supervisor.start().await.unwrap();
+        assert_eq!(events.next().await, Some("a"));
+
+        for _ in 0..3 {
+            a.send(Quit).unwrap();
+            assert_eq!(events.next().await, Some("a"));
+        }
+        assert_eq!(a.call(Ping).await.unwrap(), 1);
+    });
+}
+
+#[test]
+fn failure_during_backoff_restarts_every_child() {
+    block_on(async {
+        let (tx, mut events) = mpsc::unbounded();
+        let mut supervisor = Supervisor::new(Strategy::RestForOne)
+            .with_max_restarts(10, Duration::from_secs(10))
+            .with_backoff(Duration::from_millis(100), Duration::from_millis(100));
+        let a = member(&mut supervisor, "a", false, &tx);
+        let b = member(&mut supervisor, "b", true, &tx);
+        let c = member(&mut supervisor, "c", false, &tx);
+        supervisor.start().await.unwrap();
+        assert_eq!(
+            events.by_ref().take(3).collect::<Vec<_>>().await,
+            ["a", "b", "c"]
+        );
+
+        // `a` fails while the restart of `c` is delayed, and `b` is still stopping when the delay
+        // has elapsed
+        c.send(Fail).unwrap();
+        a.send(Fail).unwrap();
+
+        let restarted = events.by_ref().take(3).collect::<Vec<_>>();
+        let restarted = timeout(Duration::from_secs(5), restarted).await;
+        assert_eq!(restarted.unwrap(), ["a", "b", "c"]);
+        for addr in [a, b, c] {
+            let res = timeout(Duration::from_secs(5), addr.call(Ping)).await;
+            assert!(matches!(res, Ok(Ok(1))));
+        }
+    });
+}
+
+struct Tracked {
+    generation: usize,
+    events: mpsc::UnboundedSender<String>,
+}
+
+#[async_trait::async_trait]
+impl Actor for Tracked {
+    async fn started(&mut self, _ctx: &mut Context<Self>) -> Result<()> {
+        let event = format!("started {}", self.generation);
+        self.events.unbounded_send(event).ok();
+        Ok(())
+    }
+
+    async fn stopped(&mut self, _ctx: &mut Context<Self>, _reason: &StopReason) {
+        let event = format!("stopped {}", self.generation);
+        self.events.unbounded_send(event).ok();
+    }
+
+    async fn pre_restart(&mut self, ctx: &mut Context<Self>, _reason: &StopReason) {
+        let event = format!("pre_restart {}", self.generation);
+        self.events.unbounded_send(event).ok();
+        ctx.set_restart_state(self.generation);
+    }
+
+    async fn post_restart(&mut self, ctx: &mut Context<Self>) {
+        if let Some(generation) = ctx.take_restart_state::<usize>() {
+            self.generation = generation + 1;
+        }
+        let event = format!("post_restart {}", self.generation);
+        self.events.unbounded_send(event).ok();
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Fail> for Tracked {
+    async fn handle(&mut self, ctx: &mut Context<Self>, _msg: Fail) {
+        ctx.stop(Some(error::Error::msg("failed")));
+    }
+}
+
+#[test]
+fn restart_hooks_hand_over_state_in_order() {
+    block_on(async {
+        let (tx, events) = mpsc::unbounded();
+        let addr = Supervisor::start(move || Tracked {
+            generation: 0,
+            events: tx.clone(),
+        })
+        .await
+        .unwrap();
+        addr.send(Fail).unwrap();
+        addr.send(Fail).unwrap();
+
+        let events = events.take(9).collect::<Vec<_>>().await;
+        assert_eq!(
+            events,
+            [
+                "started 0",
+                "stopped 0",
+                "pre_restart 0",
+                "post_restart 1",
+                "started 1",
+                "stopped 1",
+                "pre_restart 1",
+                "post_restart 2",
+                "started 2",
+            ]
+        );
+    });
+}
+
+#[test]
+fn supervisor_does_not_start_the_event_broker() {
+    block_on(async {
+        let system = ActorSystem::new();
+        let (tx, mut events) = mpsc::unbounded();
+        let mut supervisor = Supervisor::new(Strategy::OneForOne);
+        let a = member(&mut supervisor, "a", false, &tx);
+        system.start(supervisor).await.unwrap();
+        assert_eq!(events.next().await, Some("a"));
+
+        a.send(Fail).unwrap();
+        assert_eq!(events.next().await, Some("a"));
+        assert!(system
+            .actors()
+            .iter()
+            .all(|info| !info.type_name.contains("Broker")));
+    });
+}
+
+#[test]
+fn nested_supervisors_stop_when_their_children_are_dropped() {
+    block_on(async {
+        let system = ActorSystem::new();
+        let (tx, mut events) = mpsc::unbounded();
+        let mut inner = Supervisor::new(Strategy::OneForOne);
+        let worker = member(&mut inner, "w", false, &tx);
+        let mut root = Supervisor::new(Strategy::OneForOne);
+        let inner = root.supervisor(inner);
+        let root = system.start(root).await.unwrap();
+        assert_eq!(events.next().await, Some("w"));
+        assert_eq!(system.actors().len(), 3);
+
+        drop((worker, inner, root));
+        let stopped = async {
+            while !system.actors().is_empty() {
+                sleep(Duration::from_millis(10)).await;
+            }
+        };
+        assert!(timeout(Duration::from_secs(5), stopped).await.is_ok());
+    });
+}
+
+#[test]
+fn parent_restarts_subtree_when_nested_supervisor_gives_up() {
+    block_on(async {
+        let (tx, mut events) = mpsc::unbounded();
+        let mut inner =
+            Supervisor::new(Strategy::OneForOne).with_max_restarts(1, Duration::from_secs(10));
+        let a = member(&mut inner, "a", false, &tx);
+        let b = member(&mut inner, "b", false, &tx);
+        let mut root =
+            Supervisor::new(Strategy::OneForOne).with_max_restarts(3, Duration::from_secs(10));
+        let inner = root.supervisor(inner);
+        let _root = root.start().await.unwrap();
+        assert_eq!(
+            events.by_ref().take(2).collect::<Vec<_>>().await,
+            ["a", "b"]
+        );
+
+        // Restarted by the nested supervisor
+        a.send(Fail).unwrap();
+        assert_eq!(events.next().await, Some("a"));
+
+        // The nested supervisor exceeds its limit, and is restarted with its children by the root
+        a.send(Fail).unwrap();
+        let restarted = events.by_ref().take(2).collect::<Vec<_>>();
+        let restarted = timeout(Duration::from_secs(5), restarted).await;
+        assert_eq!(restarted.unwrap(), ["a", "b"]);
+        for addr in [a, b] {
+            let res = timeout(Duration::from_secs(5), addr.call(Ping)).await;
+            assert!(matches!(res, Ok(Ok(1))));
+        }
+        drop(inner);
+    });
+}
+
+#[test]
+fn root_gives_up_and_stops_the_tree() {
+    block_on(async {
+        let (tx, mut events) = mpsc::unbounded();
+        let mut inner =
+            Supervisor::new(Strategy::OneForOne).with_max_restarts(0, Duration::from_secs(10));
+        let a = member(&mut inner, "a", false, &tx);
+        let mut root =
+            Supervisor::new(Strategy::OneForOne).with_max_restarts(0, Duration::from_secs(10));
+        let _inner = root.supervisor(inner);
+        let b = member(&mut root, "b", false, &tx);
+        let root = root.start().await.unwrap();
+        assert_eq!(
+            events.by_ref().take(2).collect::<Vec<_>>().await,
+            ["a", "b"]
+        );
+
+        a.send(Fail).unwrap();
+        for addr in [a, b] {
+            let reason = timeout(Duration::from_secs(5), addr.wait_for_stop()).await;
+            assert!(matches!(reason, Ok(StopReason::RestartLimitExceeded)));
+        }
+        let reason = timeout(Duration::from_secs(5), root.wait_for_stop()).await;
+        assert!(matches!(reason, Ok(StopReason::RestartLimitExceeded)));
+    });
+}
@@ -0,0 +1,42 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use xactor::*;
+
+static STARTS: AtomicUsize = AtomicUsize::new(0);
+
+#[message]
+struct Fail;
+
+struct Unstable;
+
+#[async_trait::async_trait]
+impl Actor for Unstable {
+    async fn started(&mut self, _ctx: &mut Context<Self>) -> Result<()> {
+        if STARTS.fetch_add(1, Ordering::SeqCst) > 0 {
+            return Err(error::Error::msg("unavailable"));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Fail> for Unstable {
+    async fn handle(&mut self, ctx: &mut Context<Self>, _msg: Fail) {
+        ctx.stop(Some(error::Error::msg("failed")));
+    }
+}
+
+// Shuts the global system down, so it runs in its own test binary
+#[test]
+fn failing_restarts_back_off_and_allow_shutdown() {
+    block_on(async {
+        let addr = Supervisor::start(|| Unstable).await.unwrap();
+        addr.send(Fail).unwrap();
+        sleep(Duration::from_millis(300)).await;
+        let starts = STARTS.load(Ordering::SeqCst);
+        assert!(starts > 1 && starts < 20, "started {} times", starts);
+
+        let report = ActorSystem::global().shutdown(Duration::from_secs(1)).await;
+        assert!(report.is_complete());
+    });
+}
@@ -0,0 +1,37 @@
+use std::time::Duration;
+use xactor::*;
+
+#[message]
+struct Tick;
+
+#[message(result = "TimerHandle")]
+struct Schedule;
+
+struct Scheduler;
+
+impl Actor for Scheduler {}
+
+#[async_trait::async_trait]
+impl Handler<Tick> for Scheduler {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Tick) {}
+}
+
+#[async_trait::async_trait]
+impl Handler<Schedule> for Scheduler {
+    async fn handle(&mut self, ctx: &mut Context<Self>, _msg: Schedule) -> TimerHandle {
+        ctx.send_later(Tick, Duration::from_secs(10))
+    }
+}
+
+#[test]
+fn canceled_timer_cannot_be_rescheduled() {
+    block_on(async {
+        let addr = Scheduler.start().await.unwrap();
+        let timer = addr.call(Schedule).await.unwrap();
+        assert!(timer.reschedule(Duration::from_secs(5)));
+
+        timer.cancel();
+        assert!(!timer.reschedule(Duration::from_secs(5)));
+        assert!(!timer.has_fired());
+    });
+}
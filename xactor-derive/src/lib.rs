@@ -9,17 +9,22 @@ use syn::{parse_macro_input, AttributeArgs, DeriveInput, Error, Meta, NestedMeta
 /// Implement an xactor message type.
 ///
 /// The return value type defaults to (), and you can specify the type with the result parameter.
+/// The priority defaults to normal, and you can set it to high with the priority parameter.
 ///
 /// # Examples
 ///
 /// ```ignore
 /// #[message(result = "i32")]
 /// struct TestMessage(i32);
+///
+/// #[message(priority = "high")]
+/// struct UrgentMessage;
 /// ```
 #[proc_macro_attribute]
 pub fn message(args: TokenStream, input: TokenStream) -> TokenStream {
     let args = parse_macro_input!(args as AttributeArgs);
     let mut result_type = quote! { () };
+    let mut priority = None;
 
     for arg in args {
         if let NestedMeta::Meta(Meta::NameValue(nv)) = arg {
@@ -33,16 +38,31 @@ pub fn message(args: TokenStream, input: TokenStream) -> TokenStream {
                             .into();
                     }
                 }
+            } else if nv.path.is_ident("priority") {
+                if let syn::Lit::Str(lit) = nv.lit {
+                    match lit.value().as_str() {
+                        "normal" => priority = Some(quote! { xactor::Priority::Normal }),
+                        "high" => priority = Some(quote! { xactor::Priority::High }),
+                        _ => {
+                            return Error::new_spanned(&lit, "Expect \"normal\" or \"high\"")
+                                .to_compile_error()
+                                .into();
+                        }
+                    }
+                }
             }
         }
     }
 
     let input = parse_macro_input!(input as DeriveInput);
     let ident = &input.ident;
+    let priority =
+        priority.map(|priority| quote! { const PRIORITY: xactor::Priority = #priority; });
     let expanded = quote! {
         #input
         impl xactor::Message for #ident {
             type Result = #result_type;
+            #priority
         }
     };
     expanded.into()
@@ -50,7 +70,9 @@ pub fn message(args: TokenStream, input: TokenStream) -> TokenStream {
 
 /// Implement an xactor main function.
 ///
-/// Wait for all actors to exit.
+/// When the main function returns, all actors are stopped with `ActorSystem::shutdown`.
+/// Services are stopped last, the actors are given `ActorSystem::DEFAULT_SHUTDOWN_TIMEOUT` to
+/// stop.
 #[proc_macro_attribute]
 pub fn main(_args: TokenStream, input: TokenStream) -> TokenStream {
     let mut input = syn::parse_macro_input!(input as syn::ItemFn);
@@ -74,7 +96,13 @@ pub fn main(_args: TokenStream, input: TokenStream) -> TokenStream {
         #input
 
         fn main() #ret {
-            xactor::block_on(__main())
+            xactor::block_on(async {
+                let res = __main().await;
+                xactor::ActorSystem::global()
+                    .shutdown(xactor::ActorSystem::DEFAULT_SHUTDOWN_TIMEOUT)
+                    .await;
+                res
+            })
         }
     };
 